@@ -0,0 +1,204 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use cargo::core::resolver::CliFeatures;
+use cargo::core::{FeatureMap, FeatureValue, Package};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// the feature cargo enables unless told otherwise
+const DEFAULT_FEATURE: &str = "default";
+
+/// A feature of the package and if it is enabled by default in the recipe
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Feature {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Provides the features of `package`, marking the ones that are enabled by
+/// the feature flags in `cli_features` or by default if none were given
+pub fn features(package: &Package, cli_features: Option<&CliFeatures>) -> Vec<Feature> {
+    let map = package.summary().features();
+    let requested = match cli_features {
+        Some(cli) if cli.all_features => map.keys().map(|f| f.to_string()).collect(),
+        Some(cli) => cli
+            .features
+            .iter()
+            .filter_map(|f| match f {
+                FeatureValue::Feature(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .chain(
+                cli.uses_default_features
+                    .then(|| DEFAULT_FEATURE.to_string()),
+            )
+            .collect(),
+        None => vec![DEFAULT_FEATURE.to_string()],
+    };
+    feature_list(map, requested)
+}
+
+/// the features of `map` with the ones turned on by the `requested` ones
+/// enabled
+fn feature_list(map: &FeatureMap, requested: Vec<String>) -> Vec<Feature> {
+    let enabled = enabled(map, requested);
+    // the recipe builds with --no-default-features so `default` is only
+    // needed as an entry when it enables dependencies rather than features
+    let keep_default = map
+        .get(DEFAULT_FEATURE)
+        .into_iter()
+        .flatten()
+        .any(|v| !matches!(v, FeatureValue::Feature(_)));
+
+    map.keys()
+        .filter(|name| keep_default || name.as_str() != DEFAULT_FEATURE)
+        .map(|name| Feature {
+            name: name.to_string(),
+            enabled: enabled.contains(name.as_str()),
+        })
+        .collect()
+}
+
+/// the features in `map` turned on by enabling the `requested` ones
+fn enabled(map: &FeatureMap, requested: Vec<String>) -> BTreeSet<String> {
+    let mut enabled = BTreeSet::new();
+    let mut pending = requested;
+    while let Some(name) = pending.pop() {
+        if !enabled.insert(name.clone()) {
+            continue;
+        }
+
+        for value in map.get(name.as_str()).into_iter().flatten() {
+            if let FeatureValue::Feature(f) = value {
+                pending.push(f.to_string());
+            }
+        }
+    }
+    enabled
+}
+
+/// Builds the `PACKAGECONFIG` entries for `features`, which are passed
+/// to cargo through `CARGO_FEATURES`
+pub fn packageconfig(features: &[Feature]) -> String {
+    if features.is_empty() {
+        return String::new();
+    }
+
+    let defaults = features
+        .iter()
+        .filter(|f| f.enabled)
+        .map(|f| f.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut lines = vec![format!("PACKAGECONFIG ??= \"{}\"", defaults)];
+    lines.extend(
+        features
+            .iter()
+            .map(|f| format!("PACKAGECONFIG[{}] = \"\"", f.name)),
+    );
+    lines.push("CARGO_FEATURES = \"${PACKAGECONFIG}\"".into());
+    lines.push(
+        "CARGO_BUILD_FLAGS += \"--no-default-features --features '${CARGO_FEATURES}'\"".into(),
+    );
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cargo::util::interning::InternedString;
+
+    fn feature_map(features: &[(&str, &[&str])]) -> FeatureMap {
+        features
+            .iter()
+            .map(|(name, values)| {
+                (
+                    InternedString::new(name),
+                    values
+                        .iter()
+                        .map(|v| FeatureValue::new(InternedString::new(v)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn enabled_features() {
+        let map = feature_map(&[
+            ("default", &["std"]),
+            ("std", &["alloc", "serde?/std"]),
+            ("alloc", &[]),
+            ("tls", &["dep:openssl"]),
+        ]);
+        let enabled = enabled(&map, vec!["default".into()]);
+        assert_eq!(
+            enabled.into_iter().collect::<Vec<_>>(),
+            vec!["alloc", "default", "std"]
+        );
+        assert!(super::enabled(&map, vec![]).is_empty());
+    }
+
+    #[test]
+    fn default_dependencies() {
+        let map = feature_map(&[("default", &["std"]), ("std", &[])]);
+        let names = |list: Vec<Feature>| {
+            list.into_iter()
+                .filter(|f| f.enabled)
+                .map(|f| f.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(feature_list(&map, vec!["default".into()])),
+            vec!["std"]
+        );
+
+        let map = feature_map(&[("default", &["dep:other", "std"]), ("std", &[])]);
+        assert_eq!(
+            names(feature_list(&map, vec!["default".into()])),
+            vec!["default", "std"]
+        );
+        assert!(names(feature_list(&map, vec![])).is_empty());
+
+        let map = feature_map(&[("default", &["serde/std"])]);
+        assert_eq!(
+            packageconfig(&feature_list(&map, vec!["default".into()])),
+            "PACKAGECONFIG ??= \"default\"\n\
+             PACKAGECONFIG[default] = \"\"\n\
+             CARGO_FEATURES = \"${PACKAGECONFIG}\"\n\
+             CARGO_BUILD_FLAGS += \"--no-default-features --features '${CARGO_FEATURES}'\"\n"
+        );
+    }
+
+    #[test]
+    fn packageconfig_entries() {
+        let features = vec![
+            Feature {
+                name: "std".into(),
+                enabled: true,
+            },
+            Feature {
+                name: "tls".into(),
+                enabled: false,
+            },
+        ];
+        assert_eq!(
+            packageconfig(&features),
+            "PACKAGECONFIG ??= \"std\"\n\
+             PACKAGECONFIG[std] = \"\"\n\
+             PACKAGECONFIG[tls] = \"\"\n\
+             CARGO_FEATURES = \"${PACKAGECONFIG}\"\n\
+             CARGO_BUILD_FLAGS += \"--no-default-features --features '${CARGO_FEATURES}'\"\n"
+        );
+        assert_eq!(packageconfig(&[]), "");
+    }
+}
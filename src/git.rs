@@ -9,13 +9,16 @@
  */
 
 use anyhow::{anyhow, Context as _};
-use cargo::util::CargoResult;
 use cargo::GlobalContext;
+use cargo::core::{GitReference, SourceId};
+use cargo::sources::git::resolve_ref;
+use cargo::util::{CargoResult, short_hash};
 use git2::{self, Repository};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::default::Default;
 use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
 
 /// basic pattern to match ssh style remote URLs
 /// so that they can be fixed up
@@ -27,18 +30,13 @@ lazy_static! {
     static ref SSH_STYLE_REMOTE: Regex = Regex::new(SSH_STYLE_REMOTE_STR).unwrap();
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub enum GitPrefix {
+    #[default]
     Git,
     GitSubmodule,
 }
 
-impl Default for GitPrefix {
-    fn default() -> Self {
-        Self::Git
-    }
-}
-
 impl Display for GitPrefix {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(
@@ -93,10 +91,11 @@ pub struct ProjectRepo {
 }
 
 impl ProjectRepo {
-    /// Attempts to guess at the upstream repo this project can be fetched from
-    pub fn new(gctx: &GlobalContext) -> CargoResult<Self> {
-        let repo = Repository::discover(gctx.cwd())
-            .context("Unable to determine git repo for this project")?;
+    /// Attempts to guess at the upstream repo the project at `path` can be
+    /// fetched from
+    pub fn new(path: &Path) -> CargoResult<Self> {
+        let repo =
+            Repository::discover(path).context("Unable to determine git repo for this project")?;
 
         let remote = repo
             .find_remote("origin")
@@ -158,6 +157,84 @@ impl ProjectRepo {
     }
 }
 
+/// the directory cargo keeps the database of the git source `source_id`
+/// in, which is named after the repo and a hash of its URL
+fn db_name(source_id: SourceId) -> String {
+    let url = source_id.canonical_url();
+    let name = url
+        .raw_canonicalized_url()
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("_empty");
+    format!("{}-{}", name, short_hash(url))
+}
+
+/// Resolves `reference` of the git source `source_id` to a full commit id
+/// using the database cargo keeps of the repo, so without fetching anything
+pub fn resolve_reference(
+    gctx: &GlobalContext,
+    source_id: SourceId,
+    reference: &GitReference,
+) -> CargoResult<String> {
+    let path = gctx
+        .git_db_path()
+        .join(db_name(source_id))
+        .into_path_unlocked();
+    let repo = Repository::open_bare(&path)
+        .with_context(|| format!("Unable to open cargo's git database {}", path.display()))?;
+    Ok(resolve_ref(reference, &repo)?.to_string())
+}
+
+/// the top of the checkout in cargo's `checkouts` directory that
+/// `manifest_path` is in, checkouts are kept in `{name}-{hash}/{short rev}`
+fn checkout_root(checkouts: &Path, manifest_path: &Path) -> Option<PathBuf> {
+    let mut components = manifest_path.strip_prefix(checkouts).ok()?.components();
+    let root = checkouts.join(components.next()?).join(components.next()?);
+    // the manifest itself has to be below it
+    components.next()?;
+    Some(root)
+}
+
+/// The checkout cargo made of a git dependency
+#[derive(Debug)]
+pub struct DependencyRepo {
+    /// the top of the checkout, which is where the fetched repo is placed
+    root: PathBuf,
+}
+
+impl DependencyRepo {
+    /// Finds the checkout in cargo's git cache that the package with the
+    /// manifest at `manifest_path` is in, if it is in one at all. This is
+    /// never a submodule of the repo or the repo of a vendored copy.
+    pub fn new(gctx: &GlobalContext, manifest_path: &Path) -> Option<Self> {
+        let checkouts = gctx.git_checkouts_path();
+        checkout_root(checkouts.as_path_unlocked(), manifest_path).map(|root| Self { root })
+    }
+
+    /// the directory of the package with the manifest at `manifest_path`
+    /// relative to the top of the checkout
+    pub fn subdir(&self, manifest_path: &Path) -> Option<String> {
+        let dir = manifest_path.parent()?.strip_prefix(&self.root).ok()?;
+        Some(
+            dir.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    }
+
+    /// the prefix to fetch the repo with, which has to include the
+    /// submodules if it has any
+    pub fn prefix(&self) -> GitPrefix {
+        if self.root.join(".gitmodules").exists() {
+            GitPrefix::GitSubmodule
+        } else {
+            GitPrefix::Git
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -258,6 +335,38 @@ mod test {
         );
     }
 
+    #[test]
+    fn checkout_subdirs() {
+        let checkouts = Path::new("/home/me/.cargo/git/checkouts");
+        let top = Path::new("/home/me/.cargo/git/checkouts/foo-1234/abcdef0");
+        let repo = |path: &Path| checkout_root(checkouts, path).map(|root| DependencyRepo { root });
+
+        let manifest = top.join("Cargo.toml");
+        let repo_top = repo(&manifest).unwrap();
+        assert_eq!(repo_top.root, top);
+        assert_eq!(repo_top.subdir(&manifest), Some("".into()));
+
+        // a crate in a sub directory or in a submodule of the repo
+        let manifest = top.join("vendor/bar/crates/bar/Cargo.toml");
+        let nested = repo(&manifest).unwrap();
+        assert_eq!(nested.root, top);
+        assert_eq!(
+            nested.subdir(&manifest),
+            Some("vendor/bar/crates/bar".into())
+        );
+
+        // vendored crates aren't in a checkout
+        assert!(repo(Path::new("/src/project/vendor/foo/Cargo.toml")).is_none());
+        assert!(repo(Path::new("/home/me/.cargo/git/checkouts/foo-1234")).is_none());
+    }
+
+    #[test]
+    fn cargo_db_name() {
+        let url = cargo::util::IntoUrl::into_url("https://example.com/gdep.git").unwrap();
+        let id = SourceId::for_git(&url, GitReference::DefaultBranch).unwrap();
+        assert_eq!(db_name(id), "gdep-33eb42b720d34673");
+    }
+
     #[test]
     fn remote_ssh_with_submodules() {
         let repo = "git@github.com:rust-lang/cargo.git";
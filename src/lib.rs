@@ -0,0 +1,810 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Generates BitBake recipes for Cargo packages.
+//!
+//! A [`RecipeGenerator`] resolves the dependencies of the packages in a
+//! workspace and provides a [`Recipe`] for each of them, which can be
+//! rendered to a BitBake recipe or inspected directly.
+
+extern crate anyhow;
+extern crate cargo;
+extern crate cargo_platform;
+extern crate cargo_util;
+extern crate git2;
+extern crate itertools;
+extern crate lazy_static;
+extern crate md5;
+extern crate minijinja;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+
+use anyhow::{Context as _, anyhow};
+use cargo::GlobalContext;
+use cargo::core::GitReference;
+use cargo::core::registry::PackageRegistry;
+use cargo::core::resolver::CliFeatures;
+use cargo::core::resolver::features::HasDevUnits;
+use cargo::core::{Package, PackageId, PackageSet, Resolve, Workspace};
+use cargo::ops;
+use cargo::util::interning::InternedString;
+use cargo::util::{CargoResult, important_paths};
+use cargo_util::paths;
+use std::collections::{HashMap, HashSet};
+use std::default::Default;
+use std::path::{Path, PathBuf};
+
+mod features;
+pub mod git;
+pub mod license;
+mod platform;
+mod recipe;
+mod registry;
+mod sysdeps;
+mod template;
+
+pub use features::Feature;
+pub use recipe::{Dependency, LicenseGroup, Project, ProjectSource, Recipe, Source};
+pub use sysdeps::SystemDeps;
+
+/// the revision that makes bitbake follow the branch of a git repository
+const AUTOREV: &str = "${AUTOREV}";
+
+/// Represents the package we are trying to generate a recipe for
+struct PackageInfo<'gctx> {
+    gctx: &'gctx GlobalContext,
+    ws: Workspace<'gctx>,
+    package: Option<String>,
+    workspace: bool,
+    /// the features requested on the command line, if any
+    cli_features: Option<CliFeatures>,
+    /// resolve without writing Cargo.lock
+    dry_run: bool,
+}
+
+impl<'gctx> PackageInfo<'gctx> {
+    /// creates our package info from the global context and the
+    /// `manifest_path`, which may not be provided
+    fn new(
+        gctx: &GlobalContext,
+        manifest_path: Option<PathBuf>,
+        package: Option<String>,
+        workspace: bool,
+        cli_features: Option<CliFeatures>,
+        dry_run: bool,
+    ) -> CargoResult<PackageInfo<'_>> {
+        let root = match manifest_path {
+            Some(path) => {
+                // cargo compares manifest paths as is so `..` has to go
+                let path = paths::normalize_path(&gctx.cwd().join(path));
+                if !path.is_file() {
+                    return Err(anyhow!("manifest path `{}` does not exist", path.display()));
+                }
+                path
+            }
+            None => important_paths::find_root_manifest_for_wd(gctx.cwd())?,
+        };
+        let ws = Workspace::new(&root, gctx)?;
+        Ok(PackageInfo {
+            gctx,
+            ws,
+            package,
+            workspace,
+            cli_features,
+            dry_run,
+        })
+    }
+
+    /// provides the current package we are working with
+    fn package(&self) -> CargoResult<&Package> {
+        self.package
+            .as_ref()
+            // Try to find the package given on the command line
+            .map(|p_name| {
+                let p_name = InternedString::new(p_name);
+                self.ws
+                    .members()
+                    .find(|p| p.name() == p_name)
+                    .ok_or_else(|| anyhow!("Package {} does not exist", p_name))
+            })
+            // Otherwise, take current one
+            .unwrap_or_else(|| self.ws.current())
+    }
+
+    /// provides every package we need to generate a recipe for, which is
+    /// either all of the workspace members or just the current package
+    fn packages(&self) -> CargoResult<Vec<&Package>> {
+        if self.workspace {
+            Ok(self.ws.members().collect())
+        } else {
+            self.package().map(|p| vec![p])
+        }
+    }
+
+    /// Generates a package registry by using the Cargo.lock or
+    /// creating one as necessary
+    fn registry(&self) -> CargoResult<PackageRegistry<'gctx>> {
+        let mut registry = self.ws.package_registry()?;
+        let sources = self
+            .packages()?
+            .iter()
+            .map(|p| p.package_id().source_id())
+            .collect::<Vec<_>>();
+        registry.add_sources(sources)?;
+        Ok(registry)
+    }
+
+    /// Resolve the packages necessary for the workspace
+    fn resolve(&self) -> CargoResult<(PackageSet<'gctx>, Resolve)> {
+        // build up our registry
+        let mut registry = self.registry()?;
+
+        // resolve our dependencies
+        let (_, resolve) = ops::resolve_ws(&self.ws, self.dry_run)?;
+
+        // unless features were requested resolve with all features set so we
+        // ensure we get all of the depends downloaded
+        let (cli_features, specs) = match self.cli_features {
+            Some(ref cli_features) => (
+                cli_features.clone(),
+                self.packages()?
+                    .iter()
+                    .map(|p| p.package_id().to_spec())
+                    .collect(),
+            ),
+            None => (CliFeatures::new_all(true), vec![]),
+        };
+        let resolve = ops::resolve_with_previous(
+            &mut registry,
+            &self.ws,
+            &cli_features,
+            HasDevUnits::No,
+            /* previous */
+            Some(&resolve),
+            /* don't avoid any */
+            None,
+            &specs,
+            /* warn? */
+            true,
+        )?;
+
+        // the packages of everything we resolved
+        let packages = ops::get_resolved_packages(&resolve, registry)?;
+
+        Ok((packages, resolve))
+    }
+
+    /// packages that are part of a workspace are a sub directory from the
+    /// top level which we need to record, this provides us with that
+    /// relative directory
+    fn rel_dir(&self, package: &Package) -> CargoResult<PathBuf> {
+        // this is the top level of the workspace
+        let root = self.ws.root().to_path_buf();
+        // path where the package's Cargo.toml lives
+        let cwd = package.root();
+
+        cwd.strip_prefix(&root)
+            .map(Path::to_path_buf)
+            .context("Unable to if Cargo.toml is in a sub directory")
+    }
+}
+
+/// What to generate recipes for and how
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// the Cargo.toml to use instead of looking for one from the current
+    /// directory
+    pub manifest_path: Option<PathBuf>,
+    /// the workspace member to generate a recipe for instead of the
+    /// current one
+    pub package: Option<String>,
+    /// generate a recipe for every member of the workspace
+    pub workspace: bool,
+    /// use the exact git revisions from Cargo.lock
+    pub reproducible: bool,
+    /// use the legacy override syntax
+    pub legacy_overrides: bool,
+    /// include the licenses of all dependencies in LICENSE
+    pub dependency_licenses: bool,
+    /// features to activate
+    pub features: Vec<String>,
+    /// activate all available features
+    pub all_features: bool,
+    /// do not activate the `default` feature
+    pub no_default_features: bool,
+    /// only include dependencies used when building for these target triples
+    pub targets: Vec<String>,
+    /// follow the branch of git dependencies with `${AUTOREV}` instead of
+    /// using the commit in Cargo.lock
+    pub autorev: bool,
+    /// resolve the dependencies without writing Cargo.lock
+    pub dry_run: bool,
+    /// where the package itself is fetched from
+    pub source: ProjectSource,
+    /// extra mappings of -sys crates to the Yocto recipes they need
+    pub sys_crates: Option<PathBuf>,
+}
+
+impl Options {
+    /// the features to resolve with if any of the feature options were set
+    fn cli_features(&self) -> CargoResult<Option<CliFeatures>> {
+        if self.features.is_empty() && !self.all_features && !self.no_default_features {
+            return Ok(None);
+        }
+
+        CliFeatures::from_command_line(&self.features, self.all_features, !self.no_default_features)
+            .map(Some)
+    }
+}
+
+/// Generates the recipes for the packages of a Cargo workspace
+pub struct RecipeGenerator<'gctx> {
+    md: PackageInfo<'gctx>,
+    options: Options,
+}
+
+impl<'gctx> RecipeGenerator<'gctx> {
+    /// Loads the workspace described by `options` using the cargo
+    /// configuration in `gctx`
+    pub fn new(gctx: &'gctx GlobalContext, options: Options) -> CargoResult<Self> {
+        let md = PackageInfo::new(
+            gctx,
+            options.manifest_path.clone(),
+            options.package.clone(),
+            options.workspace,
+            options.cli_features()?,
+            options.dry_run,
+        )?;
+        Ok(Self { md, options })
+    }
+
+    /// Resolves the dependencies and works out the recipe of each of the
+    /// packages we are generating recipes for
+    pub fn recipes(&self) -> CargoResult<Vec<Recipe>> {
+        let md = &self.md;
+        let options = &self.options;
+
+        // Resolve all dependencies (generate or use Cargo.lock as necessary)
+        let (packages, resolve) = md.resolve()?;
+
+        // only fetch the dependencies used on the platforms we build for
+        let deps = if options.targets.is_empty() {
+            resolve.iter().collect::<Vec<_>>()
+        } else {
+            let targets = platform::Targets::new(md.gctx, &md.ws, &options.targets)?;
+            let roots = md
+                .packages()?
+                .iter()
+                .map(|p| p.package_id())
+                .collect::<Vec<_>>();
+            let reachable = platform::reachable(&resolve, &packages, &roots, &targets)?;
+            resolve.iter().filter(|id| reachable.contains(id)).collect()
+        };
+
+        // build the crate URIs, these are shared by every package in the workspace
+        let dependencies = dependencies(options, md, &packages, &resolve, &deps)?;
+
+        // the system libraries the -sys crates link against
+        let sys_crates = sysdeps::SysCrates::new(options.sys_crates.as_deref())?;
+        let system_deps = sys_crates.system_deps(md.gctx, &resolve, &deps)?;
+
+        // gather up the licenses of everything that ends up in the binary
+        let dependency_licenses = if options.dependency_licenses {
+            Some(DependencyLicenses::new(md, &packages, &deps)?)
+        } else {
+            None
+        };
+
+        // attempt to figure out the git repo for this project unless it
+        // is fetched from crates.io
+        let project_repo = match options.source {
+            ProjectSource::Git => match git::ProjectRepo::new(md.ws.root()) {
+                Ok(repo) => repo,
+                Err(e) => {
+                    md.gctx.shell().warn(e)?;
+                    Default::default()
+                }
+            },
+            ProjectSource::CratesIo => Default::default(),
+        };
+
+        md.packages()?
+            .into_iter()
+            .map(|package| {
+                recipe(
+                    options,
+                    md,
+                    package,
+                    &dependencies,
+                    &system_deps,
+                    dependency_licenses.as_ref(),
+                    &project_repo,
+                )
+            })
+            .collect()
+    }
+}
+
+/// builds up the dependencies to fetch from the resolved dependency graph
+fn dependencies(
+    options: &Options,
+    md: &PackageInfo,
+    packages: &PackageSet,
+    resolve: &Resolve,
+    deps: &[PackageId],
+) -> CargoResult<Vec<Dependency>> {
+    let pkg_checksums = resolve.checksums();
+
+    // the crate:// host for each registry we have come across
+    let mut crate_hosts = HashMap::new();
+
+    let mut dependencies = vec![];
+    let mut git_deps = vec![];
+    for &pkg in deps {
+        // get the source info for this package
+        let src_id = pkg.source_id();
+        if md.ws.members().any(|m| m.package_id() == pkg) {
+            // we are generating recipes for the workspace members
+            continue;
+        } else if src_id.is_registry() {
+            // this package appears in a crate registry
+            let host = crate_hosts
+                .entry(src_id)
+                .or_insert_with(|| registry::crate_host(src_id, md.gctx));
+            dependencies.push(Dependency {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                source: Source::Registry,
+                uri: format!("crate://{}/{}/{}", host, pkg.name(), pkg.version()),
+                checksum: pkg_checksums.get(&pkg).cloned().flatten(),
+                srcrev: None,
+                fetch_name: None,
+                path: None,
+            });
+        } else if src_id.is_path() {
+            // we don't want to spit out path based
+            // entries since they're within the crate
+            // we are packaging
+            continue;
+        } else if src_id.is_git() {
+            // the checkout cargo made of the repo tells us if there are
+            // submodules to fetch and where in the repo the crate is
+            let package = packages.get_one(pkg)?;
+            let manifest_path = package.manifest_path();
+            let repo = git::DependencyRepo::new(md.gctx, manifest_path);
+            git_deps.push(GitDependency {
+                pkg,
+                url: src_id.url().to_string(),
+                rev: git_rev(options, md, pkg)?,
+                branch: match src_id.git_reference() {
+                    Some(GitReference::Branch(branch)) => Some(branch.clone()),
+                    _ => None,
+                },
+                prefix: repo
+                    .as_ref()
+                    .map_or_else(git::GitPrefix::default, |r| r.prefix()),
+                subdir: repo.as_ref().and_then(|r| r.subdir(manifest_path)),
+            });
+        } else {
+            dependencies.push(Dependency {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                source: Source::Other,
+                uri: src_id.url().to_string(),
+                checksum: None,
+                srcrev: None,
+                fetch_name: None,
+                path: None,
+            });
+        }
+    }
+
+    // crates from the same repo and revision are fetched only once
+    dependencies.extend(git_dependencies(git_deps));
+
+    // sort the crate list
+    dependencies.sort_by(|a, b| a.uri.cmp(&b.uri));
+
+    Ok(dependencies)
+}
+
+/// A crate from a git repository and where it is in the repository
+struct GitDependency {
+    pkg: PackageId,
+    url: String,
+    rev: String,
+    /// the branch the dependency follows, if it names one
+    branch: Option<String>,
+    prefix: git::GitPrefix,
+    /// the directory of the crate in the repository
+    subdir: Option<String>,
+}
+
+impl GitDependency {
+    /// what crates have to have in common to share a fetch, which is the
+    /// branch as well when it is followed with `${AUTOREV}`
+    fn fetch_key(&self) -> (&str, &str, Option<&str>) {
+        let branch = match self.rev.as_str() {
+            AUTOREV => self.branch.as_deref(),
+            _ => None,
+        };
+        (&self.url, &self.rev, branch)
+    }
+}
+
+/// builds the dependencies for the crates from git repositories, which
+/// share a single fetch per repository and revision that is named after
+/// the first crate in it
+fn git_dependencies(mut git_deps: Vec<GitDependency>) -> Vec<Dependency> {
+    git_deps.sort_by(|a, b| (a.fetch_key(), a.pkg).cmp(&(b.fetch_key(), b.pkg)));
+    git_deps
+        .chunk_by(|a, b| a.fetch_key() == b.fetch_key())
+        .flat_map(|fetch| {
+            let fetch_name = fetch[0].pkg.name().to_string();
+            let uri = git::git_to_yocto_git_url(&fetch[0].url, Some(&fetch_name), fetch[0].prefix);
+            // bitbake makes sure the revision is on the branch that is
+            // followed, which can only be named when the crates agree on it
+            let uri = match fetch[0].branch {
+                Some(ref branch) if fetch.iter().all(|d| d.branch == fetch[0].branch) => {
+                    uri.replace(";nobranch=1", &format!(";branch={}", branch))
+                }
+                _ => uri,
+            };
+            fetch.iter().map(move |dep| {
+                // the fetch is placed in a directory named after it
+                let path = match dep.subdir.as_deref() {
+                    Some("") | None => format!("${{WORKDIR}}/{}", fetch_name),
+                    Some(subdir) => format!("${{WORKDIR}}/{}/{}", fetch_name, subdir),
+                };
+                Dependency {
+                    name: dep.pkg.name().to_string(),
+                    version: dep.pkg.version().to_string(),
+                    source: Source::Git,
+                    uri: uri.clone(),
+                    checksum: None,
+                    srcrev: Some(dep.rev.clone()),
+                    fetch_name: Some(fetch_name.clone()),
+                    path: Some(path),
+                }
+            })
+        })
+        .collect()
+}
+
+/// the revision of the git dependency `pkg` to fetch
+fn git_rev(options: &Options, md: &PackageInfo, pkg: PackageId) -> CargoResult<String> {
+    let src_id = pkg.source_id();
+    if options.reproducible {
+        if let Some(precise) = src_id.precise_git_fragment() {
+            return Ok(precise.to_string());
+        }
+    }
+
+    let reference = src_id
+        .git_reference()
+        .ok_or_else(|| anyhow!("No git reference for {}", pkg))?;
+    match *reference {
+        // a full hash can be used as is
+        GitReference::Rev(ref s) if s.len() == 40 => return Ok(s.clone()),
+        // only follow a branch if asked to, as it isn't reproducible
+        GitReference::Branch(_) | GitReference::DefaultBranch if options.autorev => {
+            return Ok(AUTOREV.into());
+        }
+        _ => {}
+    }
+
+    // reduced hashes, tags and branches have to be turned into a commit id,
+    // which is preferably the one in Cargo.lock that a branch is pinned to
+    if let Some(precise) = src_id.precise_git_fragment() {
+        return Ok(precise.to_string());
+    }
+    let describe = reference
+        .pretty_ref(false)
+        .map_or_else(String::new, |r| r.to_string());
+    git::resolve_reference(md.gctx, src_id, reference)
+        .with_context(|| format!("Unable to resolve {} of git dependency {}", describe, pkg))
+}
+
+/// The licenses of all of the dependencies which are built into a package
+struct DependencyLicenses {
+    groups: Vec<LicenseGroup>,
+}
+
+impl DependencyLicenses {
+    /// reads the license of every dependency from its manifest
+    fn new(md: &PackageInfo, packages: &PackageSet, deps: &[PackageId]) -> CargoResult<Self> {
+        // workspace members and other path dependencies are part
+        // of the project itself
+        let ids = deps
+            .iter()
+            .copied()
+            .filter(|id| !id.source_id().is_path())
+            .filter(|id| !md.ws.members().any(|m| m.package_id() == *id));
+
+        let mut groups: Vec<LicenseGroup> = vec![];
+        for pkg in packages.get_many(ids)? {
+            let crate_name = format!("{}-{}", pkg.name(), pkg.version());
+            let metadata = pkg.manifest().metadata();
+            let expression = match metadata.license {
+                Some(ref lic) => match license::Expression::parse(lic) {
+                    Ok(expr) => Some(expr),
+                    Err(e) => {
+                        md.gctx
+                            .shell()
+                            .warn(format!("Unable to parse license of {}: {}", crate_name, e))?;
+                        None
+                    }
+                },
+                None => {
+                    md.gctx
+                        .shell()
+                        .warn(format!("No package.license set for {}", crate_name))?;
+                    None
+                }
+            };
+
+            let license = expression
+                .as_ref()
+                .map_or_else(|| "unknown".into(), license::Expression::to_bitbake);
+            match groups.iter_mut().find(|g| g.license == license) {
+                Some(group) => group.crates.push(crate_name),
+                None => groups.push(LicenseGroup {
+                    license,
+                    crates: vec![crate_name],
+                    expression,
+                }),
+            }
+        }
+
+        groups.sort_by(|a, b| a.license.cmp(&b.license));
+        for group in &mut groups {
+            group.crates.sort();
+        }
+
+        Ok(Self { groups })
+    }
+
+    /// combines the license of the package with that of all of its dependencies
+    fn combined(&self, package: &license::Expression) -> license::Expression {
+        license::Expression::all(
+            std::iter::once(package)
+                .chain(self.groups.iter().filter_map(|g| g.expression.as_ref()))
+                .cloned(),
+        )
+    }
+}
+
+/// Works out everything that goes into the recipe for a single package
+fn recipe(
+    options: &Options,
+    md: &PackageInfo,
+    package: &Package,
+    dependencies: &[Dependency],
+    system_deps: &SystemDeps,
+    dependency_licenses: Option<&DependencyLicenses>,
+    project_repo: &git::ProjectRepo,
+) -> CargoResult<Recipe> {
+    // license files are looked up relative to the package
+    let crate_root = package.root();
+
+    if package.name().contains('_') {
+        md.gctx
+            .shell()
+            .warn("Package name contains an underscore")?;
+    }
+
+    // root package metadata
+    let metadata = package.manifest().metadata();
+
+    // package description is used as BitBake summary
+    let summary = match metadata.description {
+        Some(ref s) => InternedString::new(&s.trim().replace("\n", " \\\n")),
+        None => {
+            md.gctx
+                .shell()
+                .warn("No package.description set in your Cargo.toml, using package.name")?;
+            package.name()
+        }
+    };
+
+    // package homepage (or source code location)
+    let homepage = match metadata.homepage {
+        Some(ref homepage) => homepage,
+        None => {
+            md.gctx
+                .shell()
+                .warn("No package.homepage set in your Cargo.toml, trying package.repository")?;
+            metadata
+                .repository
+                .as_ref()
+                .ok_or_else(|| anyhow!("No package.repository set in your Cargo.toml"))?
+        }
+    }
+    .trim();
+
+    // compute the relative directory into the repo our Cargo.toml is at,
+    // a crate from crates.io is unpacked on its own
+    let rel_dir = match options.source {
+        ProjectSource::Git => md.rel_dir(package)?,
+        ProjectSource::CratesIo => PathBuf::new(),
+    };
+
+    // package license, when it is only provided as a file then that file
+    // is the license file for whatever license we can figure out it is
+    let (license, package_lic_file) = match (&metadata.license, &metadata.license_file) {
+        (Some(license), _) => (license.clone(), None),
+        (None, Some(license_file)) => {
+            md.gctx
+                .shell()
+                .warn("No package.license set in your Cargo.toml, using package.license_file")?;
+            let lic_file = license::license_file(crate_root, &rel_dir, license_file);
+            let license = match license::detect(&crate_root.join(license_file)) {
+                Some(license) => license,
+                None => {
+                    md.gctx.shell().warn(format!(
+                        "Unable to determine the license in {}, using {}",
+                        license_file,
+                        license::UNKNOWN_LICENSE
+                    ))?;
+                    license::UNKNOWN_LICENSE.into()
+                }
+            };
+            (license, Some(lic_file))
+        }
+        (None, None) => {
+            md.gctx
+                .shell()
+                .warn("No package.license or package.license_file set in your Cargo.toml")?;
+            md.gctx
+                .shell()
+                .note(format!("Assuming {} license", license::CLOSED_LICENSE))?;
+            (license::CLOSED_LICENSE.into(), None)
+        }
+    };
+    let license = license.as_str();
+
+    // parse the SPDX expression so we know which licenses are involved
+    let license = license::Expression::parse(license)
+        .with_context(|| format!("Unable to parse package.license '{}'", license))?;
+
+    // the license files only come from the package but LICENSE covers
+    // all of the dependencies as well if requested
+    let combined_license =
+        dependency_licenses.map_or_else(|| license.clone(), |d| d.combined(&license));
+
+    // license files for the package
+    let mut lic_files = vec![];
+    // a crate from crates.io is unpacked without the rest of its workspace
+    let ws_root = match options.source {
+        ProjectSource::Git => md.ws.root(),
+        ProjectSource::CratesIo => crate_root,
+    };
+    let license_files = license::LicenseFiles::scan(crate_root, &rel_dir, ws_root);
+    let licenses = license.licenses();
+    let single_license = licenses.len() == 1;
+    for lic in &licenses {
+        let lic_file = match package_lic_file {
+            Some(ref lic_file) => Some(lic_file.clone()),
+            None => license_files.file(lic, single_license),
+        };
+        if let Some(lic_file) = lic_file {
+            lic_files.push((*lic, lic_file));
+        }
+    }
+    // a single license file for several licenses only needs to be listed once
+    let mut seen = HashSet::new();
+    lic_files.retain(|(_, file)| seen.insert(file.clone()));
+
+    // licenses Yocto doesn't ship the text of or that need to be accepted
+    let mut license_extras = vec![];
+    let yocto_licenses = combined_license.yocto_licenses();
+    for yocto_license in yocto_licenses.iter().filter(|l| !l.common) {
+        md.gctx.shell().note(format!(
+            "License {} is not one of Yocto's common-licenses",
+            yocto_license.name
+        ))?;
+        match lic_files
+            .iter()
+            .find(|(id, file)| *id == yocto_license.id && file.found())
+        {
+            Some((_, file)) => license_extras.push(format!(
+                "NO_GENERIC_LICENSE[{}] = \"{}\"",
+                yocto_license.name, file.path
+            )),
+            None => license_extras.push(format!(
+                "# FIXME: set NO_GENERIC_LICENSE[{}] to the path of the license text",
+                yocto_license.name
+            )),
+        }
+    }
+    if yocto_licenses.iter().any(|l| l.commercial) {
+        md.gctx.shell().warn(format!(
+            "License {} restricts commercial use",
+            combined_license.to_bitbake()
+        ))?;
+        license_extras.push("LICENSE_FLAGS = \"commercial\"".into());
+    }
+
+    // license data in Yocto fmt
+    let license = combined_license.to_bitbake();
+    let licenses = licenses.into_iter().map(str::to_string).collect();
+    let license_files = lic_files.into_iter().map(|(_, file)| file).collect();
+
+    // if this is not a tag we need to include some data about the version in PV so that
+    // the sstate cache remains valid
+    let git_srcpv = if !project_repo.tag && project_repo.rev.len() > 10 {
+        let mut pv_append_key = "PV:append";
+        // Override PV override with legacy syntax if flagged
+        if options.legacy_overrides {
+            pv_append_key = "PV_append";
+        }
+        // we should be using ${SRCPV} here but due to a bitbake bug we cannot. see:
+        // https://github.com/meta-rust/meta-rust/issues/136
+        format!(
+            "{} = \".AUTOINC+{}\"",
+            pv_append_key,
+            &project_repo.rev[..10]
+        )
+    } else {
+        // its a tag so nothing needed
+        "".into()
+    };
+
+    let project = match options.source {
+        ProjectSource::Git => Project {
+            source: ProjectSource::Git,
+            uri: project_repo.uri.clone(),
+            rev: project_repo.rev.clone(),
+            branch: project_repo.branch.clone(),
+            rel_dir: rel_dir.display().to_string(),
+            checksum: None,
+        },
+        ProjectSource::CratesIo => {
+            let checksum = registry::crates_io_checksum(
+                package.name().as_str(),
+                &package.version().to_string(),
+                md.gctx,
+            )?;
+            Project {
+                source: ProjectSource::CratesIo,
+                uri: format!(
+                    "crate://{}/{}/{}",
+                    registry::CRATES_IO_URL,
+                    package.name(),
+                    package.version()
+                ),
+                rev: String::new(),
+                branch: String::new(),
+                rel_dir: String::new(),
+                checksum: Some(checksum),
+            }
+        }
+    };
+
+    Ok(Recipe {
+        name: package.name().to_string(),
+        version: package.version().to_string(),
+        summary: summary.to_string(),
+        homepage: homepage.to_string(),
+        license,
+        licenses,
+        license_files,
+        license_extras,
+        dependency_licenses: dependency_licenses.map_or_else(Vec::new, |d| d.groups.clone()),
+        // let the features be toggled through PACKAGECONFIG
+        features: features::features(package, md.cli_features.as_ref()),
+        system_deps: system_deps.clone(),
+        project,
+        git_srcpv,
+        dependencies: dependencies.to_vec(),
+    })
+}
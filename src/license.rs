@@ -8,31 +8,856 @@
  * except according to those terms.
  */
 
+use anyhow::{anyhow, bail};
+use cargo::util::CargoResult;
+use lazy_static::lazy_static;
 use md5::Context;
-use std::fs::File;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
 use std::io;
-use std::path::Path;
+use std::iter::Peekable;
+use std::path::{Component, Path, PathBuf};
 
 pub const CLOSED_LICENSE: &str = "CLOSED";
 
+/// placeholder for a license we were unable to determine
+pub const UNKNOWN_LICENSE: &str = "Unknown";
+
+/// the licenses in Yocto's meta/files/common-licenses, one per line
+const COMMON_LICENSES: &str = include_str!("common-licenses");
+
+/// SPDX ids, including deprecated ones and ones with exceptions, as well
+/// as legacy Yocto names which are known under another name in Yocto
+const LICENSE_ALIASES: &[(&str, &str)] = &[
+    ("AGPL-3.0", "AGPL-3.0-only"),
+    ("AGPL-3.0+", "AGPL-3.0-or-later"),
+    ("AGPLv3", "AGPL-3.0-only"),
+    ("Apache-2", "Apache-2.0"),
+    ("GFDL-1.1", "GFDL-1.1-only"),
+    ("GFDL-1.1+", "GFDL-1.1-or-later"),
+    ("GFDL-1.2", "GFDL-1.2-only"),
+    ("GFDL-1.2+", "GFDL-1.2-or-later"),
+    ("GFDL-1.3", "GFDL-1.3-only"),
+    ("GFDL-1.3+", "GFDL-1.3-or-later"),
+    ("GPL-1.0", "GPL-1.0-only"),
+    ("GPL-1.0+", "GPL-1.0-or-later"),
+    ("GPL-2.0", "GPL-2.0-only"),
+    ("GPL-2.0+", "GPL-2.0-or-later"),
+    ("GPL-3.0", "GPL-3.0-only"),
+    ("GPL-3.0+", "GPL-3.0-or-later"),
+    ("GPLv2", "GPL-2.0-only"),
+    ("GPLv2+", "GPL-2.0-or-later"),
+    ("GPLv3", "GPL-3.0-only"),
+    ("GPLv3+", "GPL-3.0-or-later"),
+    ("LGPL-2.0", "LGPL-2.0-only"),
+    ("LGPL-2.0+", "LGPL-2.0-or-later"),
+    ("LGPL-2.1", "LGPL-2.1-only"),
+    ("LGPL-2.1+", "LGPL-2.1-or-later"),
+    ("LGPL-3.0", "LGPL-3.0-only"),
+    ("LGPL-3.0+", "LGPL-3.0-or-later"),
+    ("LGPLv2", "LGPL-2.0-only"),
+    ("LGPLv2.1", "LGPL-2.1-only"),
+    ("LGPLv3", "LGPL-3.0-only"),
+    ("MPL-2", "MPL-2.0"),
+    ("PSF", "PSF-2.0"),
+    (
+        "GPL-2.0-with-classpath-exception-2.0",
+        "GPL-2.0-with-classpath-exception",
+    ),
+    (
+        "GPL-2.0-only-with-Classpath-exception-2.0",
+        "GPL-2.0-with-classpath-exception",
+    ),
+    (
+        "GPL-2.0-with-GCC-exception-2.0",
+        "GPL-2.0-with-GCC-exception",
+    ),
+    (
+        "GPL-2.0-only-with-GCC-exception-2.0",
+        "GPL-2.0-with-GCC-exception",
+    ),
+    (
+        "GPL-2.0-or-later-with-GCC-exception-2.0",
+        "GPL-2.0-with-GCC-exception",
+    ),
+    (
+        "GPL-2.0-only-with-Linux-syscall-note",
+        "GPL-2.0-with-Linux-syscall-note",
+    ),
+    (
+        "GPL-2.0-only-with-OpenSSL-exception",
+        "GPL-2.0-with-OpenSSL-exception",
+    ),
+    (
+        "GPL-2.0-or-later-with-Autoconf-exception-2.0",
+        "GPL-2.0-with-autoconf-exception",
+    ),
+    (
+        "GPL-3.0-with-GCC-exception-3.1",
+        "GPL-3.0-with-GCC-exception",
+    ),
+    (
+        "GPL-3.0-only-with-GCC-exception-3.1",
+        "GPL-3.0-with-GCC-exception",
+    ),
+    (
+        "GPL-3.0-or-later-with-GCC-exception-3.1",
+        "GPL-3.0-with-GCC-exception",
+    ),
+    (
+        "GPL-3.0-or-later-with-Autoconf-exception-3.0",
+        "GPL-3.0-with-autoconf-exception",
+    ),
+];
+
+/// licenses which restrict commercial use so Yocto needs them to be
+/// explicitly accepted via LICENSE_FLAGS
+const COMMERCIAL_LICENSES: &[&str] = &[
+    "BUSL-1.1",
+    "CC-BY-NC-1.0",
+    "CC-BY-NC-2.0",
+    "CC-BY-NC-2.5",
+    "CC-BY-NC-3.0",
+    "CC-BY-NC-4.0",
+    "CC-BY-NC-ND-1.0",
+    "CC-BY-NC-ND-2.0",
+    "CC-BY-NC-ND-2.5",
+    "CC-BY-NC-ND-3.0",
+    "CC-BY-NC-ND-4.0",
+    "CC-BY-NC-SA-1.0",
+    "CC-BY-NC-SA-2.0",
+    "CC-BY-NC-SA-2.5",
+    "CC-BY-NC-SA-3.0",
+    "CC-BY-NC-SA-4.0",
+    "Elastic-2.0",
+    "PolyForm-Noncommercial-1.0.0",
+    "PolyForm-Small-Business-1.0.0",
+    "SSPL-1.0",
+];
+
+/// the text of the licenses we can recognize, from the SPDX license list
+const LICENSE_TEXTS: &[(&str, &str)] = &[
+    ("0BSD", include_str!("licenses/0BSD")),
+    ("Apache-2.0", include_str!("licenses/Apache-2.0")),
+    ("BSD-2-Clause", include_str!("licenses/BSD-2-Clause")),
+    ("BSD-3-Clause", include_str!("licenses/BSD-3-Clause")),
+    ("BSL-1.0", include_str!("licenses/BSL-1.0")),
+    ("CC0-1.0", include_str!("licenses/CC0-1.0")),
+    ("GPL-2.0-only", include_str!("licenses/GPL-2.0-only")),
+    ("GPL-3.0-only", include_str!("licenses/GPL-3.0-only")),
+    ("ISC", include_str!("licenses/ISC")),
+    ("LGPL-2.1-only", include_str!("licenses/LGPL-2.1-only")),
+    ("LGPL-3.0-only", include_str!("licenses/LGPL-3.0-only")),
+    ("MIT", include_str!("licenses/MIT")),
+    ("MIT-0", include_str!("licenses/MIT-0")),
+    ("MPL-2.0", include_str!("licenses/MPL-2.0")),
+    ("Unicode-3.0", include_str!("licenses/Unicode-3.0")),
+    (
+        "Unicode-DFS-2016",
+        include_str!("licenses/Unicode-DFS-2016"),
+    ),
+    ("Unlicense", include_str!("licenses/Unlicense")),
+    ("Zlib", include_str!("licenses/Zlib")),
+];
+
+/// how similar a text has to be to one of `LICENSE_TEXTS` for us to
+/// consider it to be that license
+const MATCH_THRESHOLD: f64 = 0.8;
+
+/// the file names, in lower case, that license texts are typically found in
+const LICENSE_FILE_PREFIXES: &[&str] = &["license", "licence", "copying", "unlicense"];
+
+/// files larger than this aren't going to be a license
+const MAX_LICENSE_FILE_SIZE: u64 = 256 * 1024;
+
+/// files that commonly carry the license text when a crate has no
+/// license file of its own
+const EMBEDDED_LICENSE_FILES: &[&str] = &["src/lib.rs", "src/main.rs", "README.md"];
+
+/// how licenses are usually referred to in prose (in lowercase)
+const LICENSE_PHRASES: &[(&str, &str)] = &[
+    ("apache license", "Apache-2.0"),
+    ("mit license", "MIT"),
+    ("mozilla public license", "MPL-2.0"),
+    ("boost software license", "BSL-1.0"),
+    ("zlib license", "Zlib"),
+    ("isc license", "ISC"),
+];
+
+lazy_static! {
+    /// the fingerprint of each of the license texts we know
+    static ref LICENSE_FINGERPRINTS: Vec<(&'static str, HashSet<(String, String)>)> =
+        LICENSE_TEXTS
+            .iter()
+            .map(|&(id, text)| (id, fingerprint(text)))
+            .collect();
+
+    /// the Yocto name of every license we know about, keyed by its
+    /// lower case name since SPDX ids are case insensitive
+    static ref YOCTO_NAMES: HashMap<String, &'static str> = {
+        let common = COMMON_LICENSES
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| (l.to_lowercase(), l));
+        let aliases = LICENSE_ALIASES
+            .iter()
+            .map(|&(alias, name)| (alias.to_lowercase(), name));
+        common.chain(aliases).collect()
+    };
+}
+
+/// A license as it is known to Yocto
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct YoctoLicense {
+    /// the SPDX id of the license, without any exception
+    pub id: String,
+    /// the name to use in LICENSE
+    pub name: String,
+    /// if the license is one of Yocto's common-licenses, otherwise
+    /// NO_GENERIC_LICENSE has to point to the license text
+    pub common: bool,
+    /// if the license needs LICENSE_FLAGS = "commercial"
+    pub commercial: bool,
+}
+
+impl YoctoLicense {
+    /// Looks up the SPDX license `id` with an optional `exception`
+    pub fn new(id: &str, exception: Option<&str>) -> Self {
+        // Yocto names licenses with exceptions as a single license
+        let spdx_name = match exception {
+            Some(exception) => format!("{}-with-{}", id, exception),
+            None => id.to_string(),
+        };
+
+        let known = YOCTO_NAMES.get(&spdx_name.to_lowercase()).or_else(|| {
+            // the deprecated `+` suffix means this version or any later one
+            let base = id.strip_suffix('+')?;
+            let with = exception.map_or_else(String::new, |e| format!("-with-{}", e));
+            YOCTO_NAMES
+                .get(&format!("{}-or-later{}", base, with).to_lowercase())
+                .or_else(|| YOCTO_NAMES.get(&format!("{}{}", base, with).to_lowercase()))
+        });
+        Self {
+            id: id.to_string(),
+            name: known.map_or(spdx_name, |name| name.to_string()),
+            common: known.is_some() || id == CLOSED_LICENSE,
+            commercial: COMMERCIAL_LICENSES
+                .iter()
+                .any(|l| l.eq_ignore_ascii_case(id)),
+        }
+    }
+}
+
+/// A parsed SPDX license expression
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expression {
+    /// a single license, optionally with an exception
+    License {
+        id: String,
+        exception: Option<String>,
+    },
+    /// any one of the expressions applies
+    Or(Vec<Expression>),
+    /// all of the expressions apply
+    And(Vec<Expression>),
+    /// an expression the author wrapped in parentheses
+    Group(Box<Expression>),
+}
+
+/// the pieces an SPDX license expression is made up of
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    With,
+    Id(String),
+}
+
+/// splits up a license expression into its tokens
+fn tokenize(expr: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+
+    // turns the word we have been collecting into a token
+    let flush = |word: &mut String, tokens: &mut Vec<Token>| {
+        if word.is_empty() {
+            return;
+        }
+        tokens.push(match word.to_ascii_uppercase().as_str() {
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            "WITH" => Token::With,
+            _ => Token::Id(word.clone()),
+        });
+        word.clear();
+    };
+
+    for c in expr.chars() {
+        match c {
+            '(' | ')' | '/' => {
+                flush(&mut word, &mut tokens);
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    // Cargo has historically allowed '/' to mean OR
+                    _ => Token::Or,
+                });
+            }
+            c if c.is_whitespace() => flush(&mut word, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    flush(&mut word, &mut tokens);
+
+    tokens
+}
+
+impl Expression {
+    /// Parses the SPDX license expression `expr`, as found in the
+    /// `package.license` field of a Cargo.toml
+    pub fn parse(expr: &str) -> CargoResult<Self> {
+        let mut tokens = tokenize(expr).into_iter().peekable();
+        let parsed = Self::parse_or(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(parsed),
+            Some(t) => Err(anyhow!("Unexpected {:?} in license '{}'", t, expr)),
+        }
+    }
+
+    /// OR binds the loosest so it is the top of the expression
+    fn parse_or<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> CargoResult<Self> {
+        let mut exprs = vec![Self::parse_and(tokens)?];
+        while tokens.next_if_eq(&Token::Or).is_some() {
+            exprs.push(Self::parse_and(tokens)?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expression::Or(exprs)
+        })
+    }
+
+    fn parse_and<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> CargoResult<Self> {
+        let mut exprs = vec![Self::parse_with(tokens)?];
+        while tokens.next_if_eq(&Token::And).is_some() {
+            exprs.push(Self::parse_with(tokens)?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expression::And(exprs)
+        })
+    }
+
+    fn parse_with<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> CargoResult<Self> {
+        match tokens.next() {
+            Some(Token::Open) => {
+                let inner = Self::parse_or(tokens)?;
+                match tokens.next() {
+                    Some(Token::Close) => Ok(Expression::Group(Box::new(inner))),
+                    _ => bail!("Missing closing parenthesis in license"),
+                }
+            }
+            Some(Token::Id(id)) => {
+                let exception = if tokens.next_if_eq(&Token::With).is_some() {
+                    match tokens.next() {
+                        Some(Token::Id(exception)) => Some(exception),
+                        _ => bail!("Missing exception after WITH for license {}", id),
+                    }
+                } else {
+                    None
+                };
+                Ok(Expression::License { id, exception })
+            }
+            Some(t) => Err(anyhow!("Expected a license but found {:?}", t)),
+            None => Err(anyhow!("Expected a license but the expression ended")),
+        }
+    }
+
+    /// Combines `exprs` into one expression that requires all of them,
+    /// skipping any that are repeated
+    pub fn all<I: IntoIterator<Item = Expression>>(exprs: I) -> Self {
+        let mut all: Vec<Expression> = vec![];
+        for expr in exprs {
+            if !all.iter().any(|e| e.to_bitbake() == expr.to_bitbake()) {
+                all.push(expr);
+            }
+        }
+
+        if all.len() == 1 {
+            return all.remove(0);
+        }
+
+        // keep the meaning of compound expressions inside the AND
+        Expression::And(
+            all.into_iter()
+                .map(|expr| match expr {
+                    Expression::Or(_) | Expression::And(_) => Expression::Group(Box::new(expr)),
+                    expr => expr,
+                })
+                .collect(),
+        )
+    }
+
+    /// Converts the expression to the syntax and license names BitBake
+    /// uses for `LICENSE`
+    pub fn to_bitbake(&self) -> String {
+        match self {
+            Expression::License { id, exception } => {
+                YoctoLicense::new(id, exception.as_deref()).name
+            }
+            Expression::Or(exprs) => exprs
+                .iter()
+                .map(Expression::to_bitbake)
+                .collect::<Vec<_>>()
+                .join(" | "),
+            Expression::And(exprs) => exprs
+                .iter()
+                .map(Expression::to_bitbake)
+                .collect::<Vec<_>>()
+                .join(" & "),
+            Expression::Group(expr) => format!("({})", expr.to_bitbake()),
+        }
+    }
+
+    /// The ids of every license used in the expression, without any
+    /// exceptions and in the order they first appear
+    pub fn licenses(&self) -> Vec<&str> {
+        let mut ids = vec![];
+        for (id, _) in self.terms() {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Every license as Yocto knows it that is used in the expression
+    pub fn yocto_licenses(&self) -> Vec<YoctoLicense> {
+        let mut licenses: Vec<YoctoLicense> = vec![];
+        for (id, exception) in self.terms() {
+            let license = YoctoLicense::new(id, exception);
+            if !licenses.iter().any(|l| l.name == license.name) {
+                licenses.push(license);
+            }
+        }
+        licenses
+    }
+
+    /// the license ids and their exceptions in the order they appear
+    fn terms(&self) -> Vec<(&str, Option<&str>)> {
+        match self {
+            Expression::License { id, exception } => vec![(id.as_str(), exception.as_deref())],
+            Expression::Or(exprs) | Expression::And(exprs) => {
+                exprs.iter().flat_map(Expression::terms).collect()
+            }
+            Expression::Group(expr) => expr.terms(),
+        }
+    }
+}
+
+/// An entry in LIC_FILES_CHKSUM
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct LicenseFile {
+    /// path of the file relative to the source directory
+    pub path: String,
+    /// md5 of the license text or "generateme" if it was not found
+    pub md5: String,
+    /// the first and last line of the license text when it is only
+    /// part of the file
+    pub lines: Option<(usize, usize)>,
+}
+
+impl LicenseFile {
+    /// if the license file was found and its checksum computed
+    pub fn found(&self) -> bool {
+        self.md5 != "generateme"
+    }
+}
+
+impl Display for LicenseFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.lines {
+            Some((begin, end)) => write!(
+                f,
+                "file://{};beginline={};endline={};md5={}",
+                self.path, begin, end, self.md5
+            ),
+            None => write!(f, "file://{};md5={}", self.path, self.md5),
+        }
+    }
+}
+
+/// Breaks a license text down into its pairs of adjacent words, ignoring
+/// case, punctuation and formatting, so that texts can be compared
+fn fingerprint(text: &str) -> HashSet<(String, String)> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    words
+        .windows(2)
+        .map(|w| (w[0].clone(), w[1].clone()))
+        .collect()
+}
+
+/// Attempts to determine the SPDX id of the license in `text` by
+/// comparing it to the license texts we know
+pub fn identify(text: &str) -> Option<&'static str> {
+    let print = fingerprint(text);
+    if print.is_empty() {
+        return None;
+    }
+
+    LICENSE_FINGERPRINTS
+        .iter()
+        .map(|(id, known)| {
+            // the Sørensen–Dice coefficient of the two texts
+            let common = print.intersection(known).count();
+            let score = 2.0 * common as f64 / (print.len() + known.len()) as f64;
+            (*id, score)
+        })
+        .filter(|&(_, score)| score >= MATCH_THRESHOLD)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(id, _)| id)
+}
+
+/// if the licenses `a` and `b` share the same text, which is the case
+/// for the "-only" and "-or-later" variants of a license
+fn same_text(a: &str, b: &str) -> bool {
+    let base = |id: &str| {
+        let name = YoctoLicense::new(id, None).name;
+        name.strip_suffix("-only")
+            .or_else(|| name.strip_suffix("-or-later"))
+            .map_or_else(|| name.clone(), str::to_string)
+            .to_lowercase()
+    };
+    base(a) == base(b)
+}
+
+/// A license file found next to the source code
+#[derive(Clone, Debug)]
+struct FoundLicense {
+    file: LicenseFile,
+    /// the license its text matches, if any
+    id: Option<&'static str>,
+}
+
+/// License text found inside of a file that isn't a license file
+#[derive(Clone, Debug)]
+struct EmbeddedLicense {
+    file: LicenseFile,
+    text: String,
+}
+
+impl EmbeddedLicense {
+    /// if this text looks like it is the license `license_name`
+    fn matches(&self, license_name: &str) -> bool {
+        if identify(&self.text).is_some_and(|id| same_text(id, license_name)) {
+            return true;
+        }
+
+        let text = self.text.to_lowercase();
+        let base = license_name
+            .trim_end_matches("-only")
+            .trim_end_matches("-or-later")
+            .to_lowercase();
+        mentions(&text, &base)
+            || LICENSE_PHRASES
+                .iter()
+                .filter(|(_, id)| same_text(id, license_name))
+                .any(|(phrase, _)| text.contains(phrase))
+    }
+}
+
+/// The license files found in a crate and at the top of its workspace
+#[derive(Debug)]
+pub struct LicenseFiles<'a> {
+    crate_root: &'a Path,
+    rel_dir: &'a Path,
+    found: Vec<FoundLicense>,
+    embedded: Vec<EmbeddedLicense>,
+}
+
+impl<'a> LicenseFiles<'a> {
+    /// Looks for license files in the crate at `crate_root`, which is at
+    /// `rel_dir` in the workspace, and in the workspace root `ws_root`
+    pub fn scan(crate_root: &'a Path, rel_dir: &'a Path, ws_root: &Path) -> Self {
+        let mut found = scan_dir(crate_root, rel_dir);
+        let mut embedded = scan_embedded(crate_root, rel_dir);
+        if crate_root != ws_root {
+            found.extend(scan_dir(ws_root, Path::new("")));
+            embedded.extend(scan_embedded(ws_root, Path::new("")));
+        }
+
+        Self {
+            crate_root,
+            rel_dir,
+            found,
+            embedded,
+        }
+    }
+
+    /// Provides the license file for the license `license_name`, checking
+    /// the license files we found by their text before falling back to
+    /// looking them up by name and then to license text embedded in the
+    /// source or README
+    pub fn file(&self, license_name: &str, single_license: bool) -> Option<LicenseFile> {
+        if license_name == CLOSED_LICENSE {
+            return None;
+        }
+
+        if let Some(f) = self
+            .found
+            .iter()
+            .find(|f| f.id.is_some_and(|id| same_text(id, license_name)))
+        {
+            return Some(f.file.clone());
+        }
+
+        let by_name = file(self.crate_root, self.rel_dir, license_name, single_license);
+        if by_name.as_ref().is_some_and(LicenseFile::found) {
+            return by_name;
+        }
+
+        self.embedded
+            .iter()
+            .find(|e| e.matches(license_name))
+            .or_else(|| self.embedded.first().filter(|_| single_license))
+            .map(|e| e.file.clone())
+            .or(by_name)
+    }
+}
+
+/// Checks all of the files in `dir`, which is at `rel_dir` in the
+/// workspace, that look like they are license files
+fn scan_dir(dir: &Path, rel_dir: &Path) -> Vec<FoundLicense> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut found = entries
+        .flatten()
+        .filter(|e| {
+            e.metadata()
+                .is_ok_and(|m| m.is_file() && m.len() <= MAX_LICENSE_FILE_SIZE)
+        })
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let lower = name.to_lowercase();
+            if !LICENSE_FILE_PREFIXES.iter().any(|p| lower.starts_with(p)) {
+                return None;
+            }
+
+            let text = fs::read_to_string(e.path()).ok()?;
+            Some(FoundLicense {
+                file: LicenseFile {
+                    path: rel_dir.join(&name).display().to_string(),
+                    md5: format!("{:x}", md5::compute(text.as_bytes())),
+                    lines: None,
+                },
+                id: identify(&text),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // directory order isn't stable so make our output stable
+    found.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+    found
+}
+
+/// Looks for license text in the header of the crate's source and in
+/// the license section of its README
+fn scan_embedded(dir: &Path, rel_dir: &Path) -> Vec<EmbeddedLicense> {
+    EMBEDDED_LICENSE_FILES
+        .iter()
+        .filter_map(|name| {
+            let text = fs::read_to_string(dir.join(name)).ok()?;
+            let (begin, end) = if name.ends_with(".md") {
+                readme_section(&text)?
+            } else {
+                header_comment(&text)?
+            };
+
+            let block = lines(&text, begin, end);
+            Some(EmbeddedLicense {
+                file: LicenseFile {
+                    path: rel_dir.join(name).display().to_string(),
+                    md5: format!("{:x}", md5::compute(block.as_bytes())),
+                    lines: Some((begin, end)),
+                },
+                text: block.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// the lines `begin` to `end` of `text`, counting from 1 and including
+/// their line endings like bitbake does when computing the checksum
+fn lines(text: &str, begin: usize, end: usize) -> String {
+    text.split_inclusive('\n')
+        .skip(begin - 1)
+        .take(end + 1 - begin)
+        .collect()
+}
+
+/// if `text` refers to a license
+fn is_license_text(text: &str) -> bool {
+    let text = text.to_lowercase();
+    ["license", "licence", "copyright"]
+        .iter()
+        .any(|w| text.contains(w))
+}
+
+/// if the lowercase `text` contains the lowercase `word` on its own
+fn mentions(text: &str, word: &str) -> bool {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '-');
+    text.match_indices(word).any(|(i, _)| {
+        !is_word(text[..i].chars().next_back()) && !is_word(text[i + word.len()..].chars().next())
+    })
+}
+
+/// finds the lines of the comment at the top of a Rust source file if
+/// it has anything to do with licensing
+fn header_comment(text: &str) -> Option<(usize, usize)> {
+    let mut begin = None;
+    let mut end = None;
+    let mut in_block = false;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let comment = if in_block || line.starts_with("/*") {
+            // a block comment continues until it is closed
+            in_block = !line.contains("*/");
+            true
+        } else {
+            line.starts_with("//")
+        };
+
+        if comment {
+            begin.get_or_insert(idx + 1);
+            end = Some(idx + 1);
+        } else if !line.is_empty() || begin.is_some() {
+            break;
+        }
+    }
+
+    let (begin, end) = (begin?, end?);
+    Some((begin, end)).filter(|_| is_license_text(&lines(text, begin, end)))
+}
+
+/// finds the lines of the section of a Markdown README that is titled
+/// "License", not including its heading
+fn readme_section(text: &str) -> Option<(usize, usize)> {
+    let heading_level = |line: &str| {
+        let level = line.chars().take_while(|&c| c == '#').count();
+        Some(level).filter(|&l| (1..=6).contains(&l))
+    };
+
+    let text_lines = text.lines().collect::<Vec<_>>();
+    let (heading, level) = text_lines.iter().enumerate().find_map(|(idx, line)| {
+        let level = heading_level(line)?;
+        let title = line[level..].trim().to_lowercase();
+        (title.starts_with("license") || title.starts_with("licence")).then_some((idx, level))
+    })?;
+
+    let section_end = text_lines[heading + 1..]
+        .iter()
+        .position(|line| heading_level(line).is_some_and(|l| l <= level))
+        .map_or(text_lines.len(), |pos| heading + 1 + pos);
+
+    // skip the blank lines around the section's text
+    let section = &text_lines[heading + 1..section_end];
+    let first = section.iter().position(|l| !l.trim().is_empty())?;
+    let last = section.iter().rposition(|l| !l.trim().is_empty())?;
+
+    // line numbers are 1 based and the heading precedes the section
+    Some((heading + 2 + first, heading + 2 + last))
+}
+
 /// For a given file at path `license_file`, generate the MD5 sum
 fn file_md5<P: AsRef<Path>>(license_file: P) -> Result<String, io::Error> {
     let mut file = File::open(license_file)?;
     let mut context = Context::new();
 
     io::copy(&mut file, &mut context)?;
-    Ok(format!("{:x}", context.compute()))
+    Ok(format!("{:x}", context.finalize()))
+}
+
+/// removes any `.` and `..` from `path` without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Provides the license file for the path in `package.license-file`,
+/// which is relative to the crate at `crate_root`. Cargo has already
+/// made the path relative to the crate if it was inherited from the
+/// workspace.
+pub fn license_file(crate_root: &Path, rel_dir: &Path, license_file: &str) -> LicenseFile {
+    LicenseFile {
+        path: normalize(&rel_dir.join(license_file)).display().to_string(),
+        md5: file_md5(crate_root.join(license_file)).unwrap_or_else(|_| String::from("generateme")),
+        lines: None,
+    }
+}
+
+/// Attempts to determine which license the file at `path` contains
+pub fn detect(path: &Path) -> Option<String> {
+    // try to recognize the text first
+    if let Some(id) = fs::read_to_string(path).ok().as_deref().and_then(identify) {
+        return Some(id.to_string());
+    }
+
+    // files are commonly named after the license they contain,
+    // e.g. LICENSE-MIT or COPYING.Apache-2.0
+    let name = path.file_name()?.to_str()?;
+    let id = ["LICENSE-", "LICENCE-", "COPYING-", "LICENSE.", "COPYING."]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    let id = id
+        .strip_suffix(".txt")
+        .or_else(|| id.strip_suffix(".md"))
+        .unwrap_or(id);
+
+    YOCTO_NAMES
+        .get(&id.to_lowercase())
+        .map(|name| name.to_string())
 }
 
 /// Given the top level of the crate at `crate_root`, attempt to find
 /// the license file based on the name of the license in `license_name`.
-pub fn file(crate_root: &Path, rel_dir: &Path, license_name: &str, single_license: bool) -> String {
+fn file(
+    crate_root: &Path,
+    rel_dir: &Path,
+    license_name: &str,
+    single_license: bool,
+) -> Option<LicenseFile> {
     // CLOSED is a special case license (case sensitive) per
     // http://www.yoctoproject.org/docs/2.3.2/mega-manual/mega-manual.html#sdk-license-detection
     // that means this is closed source and there is no license
     // under which this is released. So special case it
     if license_name == CLOSED_LICENSE {
-        return "".into();
+        return None;
     }
 
     // if the license exists at the top level then
@@ -47,30 +872,312 @@ pub fn file(crate_root: &Path, rel_dir: &Path, license_name: &str, single_licens
     let spec_abs_path = crate_root.join(spec_path);
     let simple_abs_path = crate_root.join(simple_path);
 
-    if lic_abs_path.exists() {
-        let md5sum = file_md5(lic_abs_path).unwrap_or_else(|_| String::from("generateme"));
-        format!(
-            "file://{};md5={} \\\n",
-            rel_dir.join(lic_path).display(),
-            md5sum
-        )
+    let found = if lic_abs_path.exists() {
+        Some((lic_abs_path, lic_path))
     } else if spec_abs_path.exists() {
         // the special case
-        let md5sum = file_md5(spec_abs_path).unwrap_or_else(|_| String::from("generateme"));
-        format!(
-            "file://{};md5={} \\\n",
-            rel_dir.join(spec_path).display(),
-            md5sum
-        )
+        Some((spec_abs_path, spec_path))
     } else if simple_abs_path.exists() && single_license {
-        let md5sum = file_md5(simple_abs_path).unwrap_or_else(|_| String::from("generateme"));
-        format!(
-            "file://{};md5={} \\\n",
-            rel_dir.join(simple_path).display(),
-            md5sum
-        )
+        Some((simple_abs_path, simple_path))
     } else {
+        None
+    };
+
+    Some(match found {
+        Some((abs_path, path)) => LicenseFile {
+            path: rel_dir.join(path).display().to_string(),
+            md5: file_md5(abs_path).unwrap_or_else(|_| String::from("generateme")),
+            lines: None,
+        },
         // fall through
-        format!("file://{};md5=generateme \\\n", license_name)
+        None => LicenseFile {
+            path: license_name.to_string(),
+            md5: String::from("generateme"),
+            lines: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitbake(expr: &str) -> String {
+        Expression::parse(expr).unwrap().to_bitbake()
+    }
+
+    #[test]
+    fn single_license() {
+        assert_eq!(bitbake("MIT"), "MIT");
+    }
+
+    #[test]
+    fn legacy_slash() {
+        assert_eq!(bitbake("MIT/Apache-2.0"), "MIT | Apache-2.0");
+        assert_eq!(bitbake("MIT / Apache-2.0"), "MIT | Apache-2.0");
+    }
+
+    #[test]
+    fn or_expression() {
+        assert_eq!(bitbake("MIT OR Apache-2.0"), "MIT | Apache-2.0");
+    }
+
+    #[test]
+    fn and_expression() {
+        assert_eq!(bitbake("MIT AND BSD-3-Clause"), "MIT & BSD-3-Clause");
+    }
+
+    #[test]
+    fn with_exception() {
+        assert_eq!(
+            bitbake("Apache-2.0 WITH LLVM-exception"),
+            "Apache-2.0-with-LLVM-exception"
+        );
+    }
+
+    #[test]
+    fn parentheses_kept() {
+        assert_eq!(bitbake("(MIT AND BSD-3-Clause)"), "(MIT & BSD-3-Clause)");
+        assert_eq!(
+            bitbake("(MIT OR Apache-2.0) AND Unicode-DFS-2016"),
+            "(MIT | Apache-2.0) & Unicode-DFS-2016"
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let expr = Expression::parse("MIT AND BSD-3-Clause OR Apache-2.0").unwrap();
+        assert_eq!(
+            expr,
+            Expression::Or(vec![
+                Expression::And(vec![
+                    Expression::License {
+                        id: "MIT".into(),
+                        exception: None
+                    },
+                    Expression::License {
+                        id: "BSD-3-Clause".into(),
+                        exception: None
+                    },
+                ]),
+                Expression::License {
+                    id: "Apache-2.0".into(),
+                    exception: None
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn licenses_in_expression() {
+        let expr =
+            Expression::parse("(Apache-2.0 WITH LLVM-exception OR MIT) AND MIT AND Zlib").unwrap();
+        assert_eq!(expr.licenses(), vec!["Apache-2.0", "MIT", "Zlib"]);
+    }
+
+    #[test]
+    fn yocto_names() {
+        assert_eq!(bitbake("GPL-2.0"), "GPL-2.0-only");
+        assert_eq!(bitbake("gpl-2.0+ OR mit"), "GPL-2.0-or-later | MIT");
+        assert_eq!(
+            bitbake("GPL-3.0-or-later WITH GCC-exception-3.1"),
+            "GPL-3.0-with-GCC-exception"
+        );
+    }
+
+    #[test]
+    fn or_later_suffix() {
+        let license = YoctoLicense::new("MPL-2.0+", None);
+        assert_eq!(license.name, "MPL-2.0");
+        assert!(license.common);
+        assert_eq!(
+            bitbake("LGPL-2.1+ OR Apache-2.0+"),
+            "LGPL-2.1-or-later | Apache-2.0"
+        );
+        assert_eq!(
+            bitbake("GPL-2.0+ WITH GCC-exception-2.0"),
+            "GPL-2.0-with-GCC-exception"
+        );
+        assert!(!YoctoLicense::new("Acme-1.0+", None).common);
+    }
+
+    #[test]
+    fn common_licenses() {
+        let license = YoctoLicense::new("Apache-2.0", Some("LLVM-exception"));
+        assert_eq!(license.name, "Apache-2.0-with-LLVM-exception");
+        assert!(license.common);
+        assert!(!license.commercial);
+        assert!(YoctoLicense::new(CLOSED_LICENSE, None).common);
+    }
+
+    #[test]
+    fn unknown_licenses() {
+        let license = YoctoLicense::new("LicenseRef-Acme", None);
+        assert_eq!(license.name, "LicenseRef-Acme");
+        assert!(!license.common);
+        let license = YoctoLicense::new("BUSL-1.1", None);
+        assert!(!license.common);
+        assert!(license.commercial);
+    }
+
+    #[test]
+    fn yocto_licenses_deduplicated() {
+        let expr = Expression::parse("(GPL-2.0 OR GPL-2.0-only) AND MIT").unwrap();
+        let names = expr
+            .yocto_licenses()
+            .into_iter()
+            .map(|l| l.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["GPL-2.0-only", "MIT"]);
+    }
+
+    #[test]
+    fn all_expressions() {
+        let exprs = [
+            "MIT OR Apache-2.0",
+            "MIT",
+            "MIT OR Apache-2.0",
+            "MIT",
+            "Zlib",
+        ]
+        .iter()
+        .map(|e| Expression::parse(e).unwrap());
+        assert_eq!(
+            Expression::all(exprs).to_bitbake(),
+            "(MIT | Apache-2.0) & MIT & Zlib"
+        );
+
+        let single = Expression::parse("MIT OR Apache-2.0").unwrap();
+        assert_eq!(
+            Expression::all(vec![single]).to_bitbake(),
+            "MIT | Apache-2.0"
+        );
+    }
+
+    #[test]
+    fn license_file_paths() {
+        assert_eq!(
+            normalize(Path::new("crates/foo/../../LICENSE")),
+            Path::new("LICENSE")
+        );
+        assert_eq!(
+            normalize(Path::new("./LICENSE.txt")),
+            Path::new("LICENSE.txt")
+        );
+        assert_eq!(normalize(Path::new("../LICENSE")), Path::new("../LICENSE"));
+    }
+
+    #[test]
+    fn detect_by_name() {
+        assert_eq!(detect(Path::new("LICENSE-MIT")), Some("MIT".into()));
+        assert_eq!(
+            detect(Path::new("licenses/LICENSE.apache-2.0.txt")),
+            Some("Apache-2.0".into())
+        );
+        assert_eq!(detect(Path::new("LICENSE.txt")), None);
+    }
+
+    #[test]
+    fn identify_license_texts() {
+        assert_eq!(identify(include_str!("../LICENSE-MIT")), Some("MIT"));
+        assert_eq!(
+            identify(include_str!("../LICENSE-APACHE")),
+            Some("Apache-2.0")
+        );
+        assert_eq!(identify("This is not a license at all"), None);
+        assert_eq!(identify(""), None);
+    }
+
+    #[test]
+    fn identify_similar_licenses() {
+        for (id, text) in LICENSE_TEXTS {
+            assert_eq!(identify(text), Some(*id));
+        }
+    }
+
+    #[test]
+    fn scan_crate_and_workspace() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let files = LicenseFiles::scan(root, Path::new(""), root);
+        let apache = files.file("Apache-2.0", false).unwrap();
+        assert_eq!(apache.path, "LICENSE-APACHE");
+        assert!(apache.found());
+        let mit = files.file("MIT", false).unwrap();
+        assert_eq!(mit.path, "LICENSE-MIT");
+        assert!(files.file(CLOSED_LICENSE, true).is_none());
+        assert!(!files.file("Zlib", false).unwrap().found());
+    }
+
+    #[test]
+    fn same_license_text() {
+        assert!(same_text("GPL-2.0-only", "GPL-2.0-or-later"));
+        assert!(same_text("GPL-2.0-only", "GPL-2.0+"));
+        assert!(!same_text("GPL-2.0-only", "LGPL-2.1-only"));
+    }
+
+    #[test]
+    fn source_header() {
+        let text = "\n/*\n * Copyright 2020 Someone\n *\n * Licensed under the MIT license\n */\n\nfn main() {}\n";
+        assert_eq!(header_comment(text), Some((2, 6)));
+        assert_eq!(
+            lines(text, 2, 6),
+            "/*\n * Copyright 2020 Someone\n *\n * Licensed under the MIT license\n */\n"
+        );
+        assert_eq!(
+            header_comment("//! A crate\n//! that does things\nfn main() {}\n"),
+            None
+        );
+        assert_eq!(
+            header_comment("// SPDX-License-Identifier: MIT\nuse std::fs;\n"),
+            Some((1, 1))
+        );
+    }
+
+    #[test]
+    fn readme_license_section() {
+        let text = "# foo\n\nA crate.\n\n## License\n\nfoo is licensed under the zlib License.\n\n## Contributing\n\nYes.\n";
+        assert_eq!(readme_section(text), Some((7, 7)));
+        assert_eq!(
+            readme_section("# foo\n\n## Licence\nMIT\n\nor Apache\n"),
+            Some((4, 6))
+        );
+        assert_eq!(readme_section("# foo\n\n## Licenses\n\n"), None);
+        assert_eq!(readme_section("# foo\n"), None);
+    }
+
+    #[test]
+    fn embedded_license_matches() {
+        let embedded = EmbeddedLicense {
+            file: LicenseFile {
+                path: "README.md".into(),
+                md5: "abc".into(),
+                lines: Some((7, 7)),
+            },
+            text: "Licensed under the Apache License, Version 2.0 or the MIT license".into(),
+        };
+        assert!(embedded.matches("Apache-2.0"));
+        assert!(embedded.matches("MIT"));
+        assert!(!embedded.matches("ISC"));
+        assert_eq!(
+            embedded.file.to_string(),
+            "file://README.md;beginline=7;endline=7;md5=abc"
+        );
+    }
+
+    #[test]
+    fn mentions_whole_words() {
+        assert!(mentions("released under mit.", "mit"));
+        assert!(mentions("(gpl-2.0)", "gpl-2.0"));
+        assert!(!mentions("submitted", "mit"));
+        assert!(!mentions("lgpl-2.0", "gpl-2.0"));
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(Expression::parse("").is_err());
+        assert!(Expression::parse("MIT OR").is_err());
+        assert!(Expression::parse("(MIT").is_err());
+        assert!(Expression::parse("MIT)").is_err());
+        assert!(Expression::parse("Apache-2.0 WITH").is_err());
     }
 }
@@ -10,137 +10,23 @@
 
 extern crate anyhow;
 extern crate cargo;
-extern crate git2;
-extern crate itertools;
+extern crate cargo_bitbake;
 extern crate lazy_static;
-extern crate md5;
 extern crate regex;
+extern crate similar;
 extern crate structopt;
 
-use anyhow::{Context as _, anyhow};
-use cargo::core::registry::PackageRegistry;
-use cargo::core::resolver::CliFeatures;
-use cargo::core::resolver::features::HasDevUnits;
-use cargo::core::{GitReference, PackageIdSpec};
-use cargo::core::{Package, PackageSet, Resolve, Workspace};
-use cargo::ops;
-use cargo::util::interning::InternedString;
-use cargo::util::{CargoResult, important_paths};
+use anyhow::anyhow;
+use cargo::util::CargoResult;
 use cargo::{CliResult, GlobalContext};
-use itertools::Itertools;
-use std::default::Default;
-use std::env;
-use std::fs::OpenOptions;
+use cargo_bitbake::{Options, ProjectSource, Recipe, RecipeGenerator};
+use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
 
-mod git;
-mod license;
-
-const CRATES_IO_URL: &str = "crates.io";
-
-/// Represents the package we are trying to generate a recipe for
-struct PackageInfo<'gctx> {
-    _gctx: &'gctx GlobalContext,
-    current_manifest: PathBuf,
-    ws: Workspace<'gctx>,
-    package: Option<String>,
-}
-
-impl<'gctx> PackageInfo<'gctx> {
-    /// creates our package info from the global context and the
-    /// `manifest_path`, which may not be provided
-    fn new(
-        gctx: &GlobalContext,
-        manifest_path: Option<String>,
-        package: Option<String>,
-    ) -> CargoResult<PackageInfo> {
-        let manifest_path = manifest_path.map_or_else(|| gctx.cwd().to_path_buf(), PathBuf::from);
-        let root = important_paths::find_root_manifest_for_wd(&manifest_path)?;
-        let ws = Workspace::new(&root, gctx)?;
-        Ok(PackageInfo {
-            _gctx: gctx,
-            current_manifest: root,
-            ws,
-            package,
-        })
-    }
-
-    /// provides the current package we are working with
-    fn package(&self) -> CargoResult<&Package> {
-        self.package
-            .as_ref()
-            // Try to find the package given on the command line
-            .map(|p_name| {
-                let p_name = InternedString::new(&p_name);
-                self.ws
-                    .members()
-                    .find(|p| p.name() == p_name)
-                    .ok_or_else(|| anyhow!("Package {} does not exist", p_name))
-            })
-            // Otherwise, take current one
-            .unwrap_or_else(|| self.ws.current())
-    }
-
-    /// Generates a package registry by using the Cargo.lock or
-    /// creating one as necessary
-    fn registry(&self) -> CargoResult<PackageRegistry<'gctx>> {
-        let mut registry = self.ws.package_registry()?;
-        let package = self.package()?;
-        registry.add_sources(vec![package.package_id().source_id()])?;
-        Ok(registry)
-    }
-
-    /// Resolve the packages necessary for the workspace
-    fn resolve(&self) -> CargoResult<(PackageSet<'gctx>, Resolve)> {
-        // build up our registry
-        let mut registry = self.registry()?;
-
-        // resolve our dependencies
-        let dry_run = false;
-        let (packages, resolve) = ops::resolve_ws(&self.ws, dry_run)?;
-
-        // resolve with all features set so we ensure we get all of the depends downloaded
-        let resolve = ops::resolve_with_previous(
-            &mut registry,
-            &self.ws,
-            /* resolve it all */
-            &CliFeatures::new_all(true),
-            HasDevUnits::No,
-            /* previous */
-            Some(&resolve),
-            /* don't avoid any */
-            None,
-            /* specs */
-            &[],
-            /* warn? */
-            true,
-        )?;
-
-        Ok((packages, resolve))
-    }
-
-    /// packages that are part of a workspace are a sub directory from the
-    /// top level which we need to record, this provides us with that
-    /// relative directory
-    fn rel_dir(&self) -> CargoResult<PathBuf> {
-        // this is the top level of the workspace
-        let root = self.ws.root().to_path_buf();
-        // path where our current package's Cargo.toml lives
-        let cwd = self.current_manifest.parent().ok_or_else(|| {
-            anyhow!(
-                "Could not get parent of directory '{}'",
-                self.current_manifest.display()
-            )
-        })?;
-
-        cwd.strip_prefix(&root)
-            .map(Path::to_path_buf)
-            .context("Unable to if Cargo.toml is in a sub directory")
-    }
-}
+mod update;
 
 #[derive(StructOpt, Debug)]
 struct Args {
@@ -163,6 +49,108 @@ struct Args {
     /// Package to generate
     #[structopt(short = "p", long = "--package")]
     package: Option<String>,
+
+    /// Path to Cargo.toml
+    #[structopt(long = "--manifest-path", parse(from_os_str))]
+    manifest_path: Option<PathBuf>,
+
+    /// Require Cargo.lock and cache are up to date
+    #[structopt(long = "--frozen")]
+    frozen: bool,
+
+    /// Require Cargo.lock is up to date
+    #[structopt(long = "--locked")]
+    locked: bool,
+
+    /// Run without accessing the network
+    #[structopt(long = "--offline")]
+    offline: bool,
+
+    /// Template to use for the recipe instead of the built-in one
+    #[structopt(long = "--template", parse(from_os_str))]
+    template: Option<PathBuf>,
+
+    /// Write the crate SRC_URI entries and checksums to {name}-crates.inc
+    #[structopt(long = "--crates-inc")]
+    crates_inc: bool,
+
+    /// Include the licenses of all dependencies in LICENSE
+    #[structopt(long = "--dependency-licenses")]
+    dependency_licenses: bool,
+
+    /// Generate a recipe for every member of the workspace
+    #[structopt(long = "--workspace", conflicts_with = "package")]
+    workspace: bool,
+
+    /// Check that the existing recipe is up to date instead of writing it
+    #[structopt(long = "--check")]
+    check: bool,
+
+    /// Update the blocks generated from Cargo.lock in an existing recipe
+    /// and keep everything else in it
+    #[structopt(long = "--update")]
+    update: bool,
+
+    /// Space or comma separated list of features to activate
+    #[structopt(long = "--features", number_of_values = 1)]
+    features: Vec<String>,
+
+    /// Activate all available features
+    #[structopt(long = "--all-features")]
+    all_features: bool,
+
+    /// Do not activate the `default` feature
+    #[structopt(long = "--no-default-features")]
+    no_default_features: bool,
+
+    /// Only include dependencies used when building for this target triple
+    #[structopt(long = "--target", number_of_values = 1)]
+    targets: Vec<String>,
+
+    /// TOML file mapping -sys crates to the Yocto recipes, classes and
+    /// environment they need, in addition to the built-in mappings
+    #[structopt(long = "--sys-crates", parse(from_os_str))]
+    sys_crates: Option<PathBuf>,
+
+    /// Follow the branch of git dependencies with ${AUTOREV} instead of
+    /// pinning them to the commit in Cargo.lock
+    #[structopt(long = "--autorev")]
+    autorev: bool,
+
+    /// Fetch the package itself from its git repository or the crate
+    /// published on crates.io
+    #[structopt(long = "--source", default_value = "git", possible_values = &["git", "crates-io"])]
+    source: String,
+
+    /// Write the recipe as BitBake or as a JSON description of it
+    #[structopt(long = "--format", default_value = "bitbake", possible_values = &["bitbake", "json"])]
+    format: String,
+}
+
+impl Args {
+    /// the options for the recipe generator
+    fn generator_options(&self) -> Options {
+        Options {
+            manifest_path: self.manifest_path.clone(),
+            package: self.package.clone(),
+            workspace: self.workspace,
+            reproducible: self.reproducible,
+            legacy_overrides: self.legacy_overrides,
+            dependency_licenses: self.dependency_licenses,
+            features: self.features.clone(),
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+            targets: self.targets.clone(),
+            autorev: self.autorev,
+            // checking must not leave anything behind
+            dry_run: self.check,
+            source: match self.source.as_str() {
+                "crates-io" => ProjectSource::CratesIo,
+                _ => ProjectSource::Git,
+            },
+            sys_crates: self.sys_crates.clone(),
+        }
+    }
 }
 
 #[derive(StructOpt, Debug)]
@@ -184,7 +172,7 @@ fn main() {
     let Opt::Bitbake(opt) = Opt::from_args();
     let result = real_main(opt, &mut gctx);
     if let Err(e) = result {
-        cargo::exit_with_error(e, &mut *gctx.shell());
+        cargo::exit_with_error(e, &mut gctx.shell());
     }
 }
 
@@ -194,12 +182,9 @@ fn real_main(options: Args, gctx: &mut GlobalContext) -> CliResult {
         options.quiet,
         /* color */
         None,
-        /* frozen */
-        false,
-        /* locked */
-        false,
-        /* offline */
-        false,
+        options.frozen,
+        options.locked,
+        options.offline,
         /* target dir */
         &None,
         /* unstable flags */
@@ -209,239 +194,129 @@ fn real_main(options: Args, gctx: &mut GlobalContext) -> CliResult {
     )?;
 
     // Build up data about the package we are attempting to generate a recipe for
-    let md = PackageInfo::new(gctx, None, options.package)?;
-
-    // Our current package
-    let package = md.package()?;
-    let crate_root = md
-        .ws
-        .root_manifest()
-        .parent()
-        .expect("Cargo.toml must have a parent");
-
-    if package.name().contains('_') {
-        println!("Package name contains an underscore");
+    let generator = RecipeGenerator::new(gctx, options.generator_options())?;
+
+    let mut up_to_date = true;
+    for recipe in generator.recipes()? {
+        up_to_date &= write_recipe(&options, &recipe)?;
     }
 
-    // Resolve all dependencies (generate or use Cargo.lock as necessary)
-    let resolve = md.resolve()?.1;
-    let pkg_checksums = resolve.checksums();
-
-    // build the crate URIs
-    let mut src_uri_extras = vec![];
-    let mut src_uris = resolve
-        .iter()
-        .filter_map(|pkg| {
-            if let Some(Some(chksum)) = pkg_checksums.get(&pkg) {
-                src_uri_extras.push(format!(
-                    "SRC_URI[{name}-{version}.sha256sum] = \"{chksum}\"",
-                    name = pkg.name(),
-                    version = pkg.version(),
-                ));
-            }
-
-            // get the source info for this package
-            let src_id = pkg.source_id();
-            if pkg.name() == package.name() {
-                None
-            } else if src_id.is_registry() {
-                // this package appears in a crate registry
-                Some(format!(
-                    "    crate://{}/{}/{} \\\n",
-                    CRATES_IO_URL,
-                    pkg.name(),
-                    pkg.version()
-                ))
-            } else if src_id.is_path() {
-                // we don't want to spit out path based
-                // entries since they're within the crate
-                // we are packaging
-                None
-            } else if src_id.is_git() {
-                // Just use the default download method for git repositories
-                // found in the source URIs, since cargo currently cannot
-                // initialize submodules for git dependencies anyway.
-                let url = git::git_to_yocto_git_url(
-                    src_id.url().as_str(),
-                    Some(pkg.name().as_str()),
-                    git::GitPrefix::default(),
-                );
-
-                // save revision
-                src_uri_extras.push(format!("SRCREV_FORMAT .= \"_{}\"", pkg.name()));
-
-                let precise = if options.reproducible {
-                    src_id.precise_git_fragment()
-                } else {
-                    None
-                };
-
-                let rev = if let Some(precise) = precise {
-                    precise
-                } else {
-                    match *src_id.git_reference()? {
-                        GitReference::Tag(ref s) => s,
-                        GitReference::Rev(ref s) => {
-                            if s.len() == 40 {
-                                // avoid reduced hashes
-                                s
-                            } else {
-                                let precise = src_id.precise_git_fragment();
-                                if let Some(p) = precise {
-                                    p
-                                } else {
-                                    panic!("cannot find rev in correct format!");
-                                }
-                            }
-                        }
-                        GitReference::Branch(ref s) => {
-                            if s == "master" {
-                                "${AUTOREV}"
-                            } else {
-                                s
-                            }
-                        }
-                        GitReference::DefaultBranch => "${AUTOREV}",
-                    }
-                };
-
-                src_uri_extras.push(format!("SRCREV_{} = \"{}\"", pkg.name(), rev));
-                // instruct Cargo where to find this
-                src_uri_extras.push(format!(
-                    "EXTRA_OECARGO_PATHS += \"${{WORKDIR}}/{}\"",
-                    pkg.name()
-                ));
-
-                Some(format!("    {} \\\n", url))
-            } else {
-                Some(format!("    {} \\\n", src_id.url()))
-            }
-        })
-        .collect::<Vec<String>>();
-
-    // sort the crate list
-    src_uris.sort();
-
-    // root package metadata
-    let metadata = package.manifest().metadata();
-
-    // package description is used as BitBake summary
-    let summary = metadata.description.as_ref().map_or_else(
-        || {
-            println!("No package.description set in your Cargo.toml, using package.name");
-            package.name()
-        },
-        |s| cargo::util::interning::InternedString::new(&s.trim().replace("\n", " \\\n")),
-    );
+    if !up_to_date {
+        return Err(anyhow!("Recipe is out of date, run cargo bitbake to update it").into());
+    }
 
-    // package homepage (or source code location)
-    let homepage = metadata
-        .homepage
-        .as_ref()
-        .map_or_else(
-            || {
-                println!("No package.homepage set in your Cargo.toml, trying package.repository");
-                metadata
-                    .repository
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("No package.repository set in your Cargo.toml"))
-            },
-            Ok,
-        )?
-        .trim();
-
-    // package license
-    let license = metadata.license.as_ref().map_or_else(
-        || {
-            println!("No package.license set in your Cargo.toml, trying package.license_file");
-            metadata.license_file.as_ref().map_or_else(
-                || {
-                    println!("No package.license_file set in your Cargo.toml");
-                    println!("Assuming {} license", license::CLOSED_LICENSE);
-                    license::CLOSED_LICENSE
-                },
-                String::as_str,
-            )
-        },
-        String::as_str,
-    );
+    Ok(())
+}
 
-    // compute the relative directory into the repo our Cargo.toml is at
-    let rel_dir = md.rel_dir()?;
-
-    // license files for the package
-    let mut lic_files = vec![];
-    let licenses: Vec<&str> = license.split('/').collect();
-    let single_license = licenses.len() == 1;
-    for lic in licenses {
-        lic_files.push(format!(
-            "    {}",
-            license::file(crate_root, &rel_dir, lic, single_license)
-        ));
+/// Writes out the BitBake recipe for a single package
+fn write_recipe(options: &Args, recipe: &Recipe) -> CargoResult<bool> {
+    if options.format == "json" {
+        let path = PathBuf::from(format!("{}_{}.json", recipe.name, recipe.version));
+        return output(options, &path, &format!("{}\n", recipe.to_json()?));
     }
 
-    // license data in Yocto fmt
-    let license = license.split('/').map(str::trim).join(" | ");
-
-    // attempt to figure out the git repo for this project
-    let project_repo = git::ProjectRepo::new(gctx).unwrap_or_else(|e| {
-        println!("{}", e);
-        Default::default()
-    });
-
-    // if this is not a tag we need to include some data about the version in PV so that
-    // the sstate cache remains valid
-    let git_srcpv = if !project_repo.tag && project_repo.rev.len() > 10 {
-        let mut pv_append_key = "PV:append";
-        // Override PV override with legacy syntax if flagged
-        if options.legacy_overrides {
-            pv_append_key = "PV_append";
-        }
-        // we should be using ${SRCPV} here but due to a bitbake bug we cannot. see:
-        // https://github.com/meta-rust/meta-rust/issues/136
-        format!(
-            "{} = \".AUTOINC+{}\"",
-            pv_append_key,
-            &project_repo.rev[..10]
-        )
+    // if every file we generate matches what is already there
+    let mut up_to_date = true;
+
+    // when the crates live in their own include file the recipe only
+    // carries what does not come from a crate registry
+    let template = options.template.as_deref();
+    let contents = if options.crates_inc {
+        let (contents, inc) = recipe.render_with_crates_inc(template)?;
+        up_to_date &= output(options, Path::new(&recipe.crates_inc_file_name()), &inc)?;
+        contents
     } else {
-        // its a tag so nothing needed
-        "".into()
+        recipe.render(template)?
     };
 
     // build up the path
-    let recipe_path = PathBuf::from(format!("{}_{}.bb", package.name(), package.version()));
+    let recipe_path = PathBuf::from(recipe.file_name());
+
+    // carry over any changes made to the recipe we are updating
+    let existing = if options.update {
+        update::find_recipe(&recipe.name)?
+    } else {
+        None
+    };
+    let contents = match existing {
+        Some(ref path) => {
+            let existing = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Unable to read {} with: {}", path.display(), e))?;
+            update::merge(&existing, &contents)
+        }
+        None => contents,
+    };
+    up_to_date &= output(options, &recipe_path, &contents)?;
+
+    // the version is part of the file name so a new version replaces the old recipe
+    if let Some(path) = existing.filter(|path| *path != recipe_path) {
+        if options.check {
+            println!("Out of date: {} is for a different version", path.display());
+            up_to_date = false;
+        } else {
+            fs::remove_file(&path)
+                .map_err(|e| anyhow!("Unable to remove {} with: {}", path.display(), e))?;
+            println!("Removed: {}", path.display());
+        }
+    }
+
+    Ok(up_to_date)
+}
 
-    // Open the file where we'll write the BitBake recipe
+/// Writes `contents` out to the file at `path` or, when checking, compares
+/// them to what is already there. Returns if the file is up to date.
+fn output(options: &Args, path: &Path, contents: &str) -> CargoResult<bool> {
+    if options.check {
+        check_file(path, contents)
+    } else {
+        write_file(path, contents)?;
+        Ok(true)
+    }
+}
+
+/// Compares `contents` with the file at `path`, printing a unified diff of
+/// what would change if they differ
+fn check_file(path: &Path, contents: &str) -> CargoResult<bool> {
+    // a missing file is the same as an empty one that needs everything added
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow!("Unable to read {} with: {}", path.display(), e)),
+    };
+
+    if existing == contents {
+        println!("Up to date: {}", path.display());
+        return Ok(true);
+    }
+
+    let name = path.display().to_string();
+    print!(
+        "{}",
+        similar::TextDiff::from_lines(existing.as_str(), contents)
+            .unified_diff()
+            .header(&name, &name)
+    );
+    println!("Out of date: {}", path.display());
+
+    Ok(false)
+}
+
+/// Writes `contents` out to the file at `path`, replacing anything already there
+fn write_file(path: &Path, contents: &str) -> CargoResult<()> {
+    // Open the file where we'll write the BitBake data
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(&recipe_path)
+        .open(path)
         // CliResult accepts only failure::Error, not failure::Context
-        .map_err(|e| anyhow!("Unable to open bitbake recipe file with: {}", e))?;
+        .map_err(|e| anyhow!("Unable to open {} with: {}", path.display(), e))?;
 
     // write the contents out
-    write!(
-        file,
-        include_str!("bitbake.template"),
-        name = package.name(),
-        version = package.version(),
-        summary = summary,
-        homepage = homepage,
-        license = license,
-        lic_files = lic_files.join(""),
-        src_uri = src_uris.join(""),
-        src_uri_extras = src_uri_extras.join("\n"),
-        project_rel_dir = rel_dir.display(),
-        project_src_uri = project_repo.uri,
-        project_src_rev = project_repo.rev,
-        git_srcpv = git_srcpv,
-        cargo_bitbake_ver = env!("CARGO_PKG_VERSION"),
-    )
-    .map_err(|e| anyhow!("Unable to write to bitbake recipe file with: {}", e))?;
-
-    println!("Wrote: {}", recipe_path.display());
+    file.write_all(contents.as_bytes())
+        .map_err(|e| anyhow!("Unable to write to {} with: {}", path.display(), e))?;
+
+    println!("Wrote: {}", path.display());
 
     Ok(())
 }
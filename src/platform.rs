@@ -0,0 +1,176 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use anyhow::{Context as _, anyhow};
+use cargo::GlobalContext;
+use cargo::core::dependency::DepKind;
+use cargo::core::{PackageId, PackageSet, Resolve, Workspace};
+use cargo::util::CargoResult;
+use cargo_platform::{Cfg, Platform};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// A platform we build for and the `cfg` values that are set for it
+#[derive(Debug)]
+struct Target {
+    triple: String,
+    cfgs: Vec<Cfg>,
+}
+
+/// The platforms dependencies have to be built for, which are the targets
+/// requested plus the host that runs build scripts and proc-macros
+#[derive(Debug)]
+pub struct Targets {
+    targets: Vec<Target>,
+    host: Target,
+}
+
+impl Targets {
+    /// asks rustc for the `cfg` values of each of the `triples` and the host
+    pub fn new(gctx: &GlobalContext, ws: &Workspace, triples: &[String]) -> CargoResult<Self> {
+        let rustc = gctx.load_global_rustc(Some(ws))?;
+        let target = |triple: String| {
+            let output = rustc
+                .process_no_wrapper()
+                .args(&["--print", "cfg", "--target", &triple])
+                .exec_with_output()
+                .with_context(|| format!("Unable to get the cfg values of target {}", triple))?;
+            let cfgs = parse_cfgs(&String::from_utf8_lossy(&output.stdout))?;
+            Ok(Target { triple, cfgs })
+        };
+
+        Ok(Self {
+            targets: triples
+                .iter()
+                .cloned()
+                .map(target)
+                .collect::<CargoResult<_>>()?,
+            host: target(rustc.host.to_string())?,
+        })
+    }
+
+    /// if a dependency only used on `platform` is built for any of our
+    /// targets, or for the host when it is built to run on the host
+    fn matches(&self, platform: Option<&Platform>, for_host: bool) -> bool {
+        let matches = |t: &Target| platform.is_none_or(|p| p.matches(&t.triple, &t.cfgs));
+        match for_host {
+            true => matches(&self.host),
+            false => self.targets.iter().any(matches),
+        }
+    }
+}
+
+/// parses the output of `rustc --print cfg`
+fn parse_cfgs(output: &str) -> CargoResult<Vec<Cfg>> {
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| Cfg::from_str(l).map_err(|e| anyhow!("Unable to parse cfg '{}': {}", l, e)))
+        .collect()
+}
+
+/// Walks the dependency graph in `resolve` from the `roots`, only following
+/// the dependencies that are used on one of the `targets`. Build-dependencies
+/// and proc-macros, along with everything they depend on, are built for the
+/// host instead.
+pub fn reachable(
+    resolve: &Resolve,
+    packages: &PackageSet,
+    roots: &[PackageId],
+    targets: &Targets,
+) -> CargoResult<HashSet<PackageId>> {
+    let mut seen = roots.iter().map(|&id| (id, false)).collect::<HashSet<_>>();
+    let mut pending = roots.iter().map(|&id| (id, false)).collect::<Vec<_>>();
+    while let Some((pkg, for_host)) = pending.pop() {
+        for (dep_id, deps) in resolve.deps(pkg) {
+            // the platform of a dependency is that of the package using it
+            let used = deps
+                .iter()
+                .filter(|d| {
+                    d.kind() != DepKind::Development && targets.matches(d.platform(), for_host)
+                })
+                .map(|d| d.kind())
+                .collect::<Vec<_>>();
+
+            // build-dependencies always run on the host, anything else only
+            // when it is a proc-macro or used by something on the host
+            let mut contexts = vec![];
+            if used.contains(&DepKind::Build) {
+                contexts.push(true);
+            }
+            if used.contains(&DepKind::Normal) {
+                contexts.push(for_host || packages.get_one(dep_id)?.proc_macro());
+            }
+            for dep_for_host in contexts {
+                if seen.insert((dep_id, dep_for_host)) {
+                    pending.push((dep_id, dep_for_host));
+                }
+            }
+        }
+    }
+    Ok(seen.into_iter().map(|(id, _)| id).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn linux() -> Targets {
+        let cfgs = parse_cfgs(
+            "debug_assertions\n\
+             target_arch=\"aarch64\"\n\
+             target_os=\"linux\"\n\
+             target_family=\"unix\"\n\
+             unix\n",
+        )
+        .unwrap();
+        let host = parse_cfgs(
+            "target_arch=\"x86_64\"\n\
+             target_os=\"macos\"\n\
+             target_family=\"unix\"\n\
+             unix\n",
+        )
+        .unwrap();
+        Targets {
+            targets: vec![Target {
+                triple: "aarch64-unknown-linux-gnu".into(),
+                cfgs,
+            }],
+            host: Target {
+                triple: "x86_64-apple-darwin".into(),
+                cfgs: host,
+            },
+        }
+    }
+
+    #[test]
+    fn target_matches() {
+        let targets = linux();
+        let platform = |s: &str| Platform::from_str(s).unwrap();
+        assert!(targets.matches(None, false));
+        assert!(targets.matches(Some(&platform("cfg(unix)")), false));
+        assert!(targets.matches(Some(&platform("aarch64-unknown-linux-gnu")), false));
+        assert!(targets.matches(
+            Some(&platform("cfg(any(target_os = \"linux\", windows))")),
+            false
+        ));
+        assert!(!targets.matches(Some(&platform("cfg(windows)")), false));
+        assert!(!targets.matches(Some(&platform("cfg(target_os = \"macos\")")), false));
+        assert!(!targets.matches(Some(&platform("x86_64-pc-windows-msvc")), false));
+        // the host only applies to what is built to run on it
+        assert!(targets.matches(Some(&platform("cfg(target_os = \"macos\")")), true));
+        assert!(!targets.matches(Some(&platform("cfg(target_os = \"linux\")")), true));
+    }
+
+    #[test]
+    fn invalid_cfg() {
+        assert!(parse_cfgs("target_os=linux\"").is_err());
+    }
+}
@@ -0,0 +1,385 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{features, license, sysdeps, template};
+use cargo::util::CargoResult;
+use itertools::Itertools;
+use serde::Serialize;
+use std::path::Path;
+
+/// Where a dependency is fetched from
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// a crate registry such as crates.io
+    Registry,
+    /// a git repository
+    Git,
+    /// anything else, which has to be fixed up by hand
+    Other,
+}
+
+/// A package from the resolved dependency graph that has to be fetched
+#[derive(Clone, Debug, Serialize)]
+pub struct Dependency {
+    pub name: String,
+    pub version: String,
+    pub source: Source,
+    /// the SRC_URI entry to fetch it with
+    pub uri: String,
+    /// the sha256 of the crate from Cargo.lock
+    pub checksum: Option<String>,
+    /// the revision to check out of a git repository
+    pub srcrev: Option<String>,
+    /// the name of the git fetch in `SRC_URI` the crate is part of, which
+    /// is shared by every crate from the same repository and revision
+    pub fetch_name: Option<String>,
+    /// where cargo finds the crate in the fetched git repository
+    pub path: Option<String>,
+}
+
+impl Dependency {
+    /// the `SRC_URI[...sha256sum]` entry for this crate if it has a checksum
+    fn checksum_entry(&self) -> Option<String> {
+        self.checksum.as_ref().map(|chksum| {
+            format!(
+                "SRC_URI[{name}-{version}.sha256sum] = \"{chksum}\"",
+                name = self.name,
+                version = self.version,
+            )
+        })
+    }
+
+    /// the SRCREV of the git fetch the crate is part of
+    fn srcrev_entries(&self) -> Vec<String> {
+        match (&self.fetch_name, &self.srcrev) {
+            (Some(fetch_name), Some(srcrev)) => vec![
+                format!("SRCREV_FORMAT .= \"_{}\"", fetch_name),
+                format!("SRCREV_{} = \"{}\"", fetch_name, srcrev),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// instructs cargo where to find the crate in its git repository
+    fn path_entry(&self) -> Option<String> {
+        self.path
+            .as_ref()
+            .map(|path| format!("EXTRA_OECARGO_PATHS += \"{}\"", path))
+    }
+}
+
+/// The crates that are released under a license
+#[derive(Clone, Debug, Serialize)]
+pub struct LicenseGroup {
+    /// the license in Yocto fmt
+    pub license: String,
+    /// the name-version of each crate
+    pub crates: Vec<String>,
+    #[serde(skip)]
+    pub(crate) expression: Option<license::Expression>,
+}
+
+/// formats `entries` as the lines of a multi-line BitBake variable
+fn multiline(entries: &[String]) -> String {
+    entries.iter().map(|e| format!("    {} \\\n", e)).collect()
+}
+
+/// Where the package itself is fetched from
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProjectSource {
+    /// the git repository the package is in
+    #[default]
+    Git,
+    /// the crate published on crates.io
+    CratesIo,
+}
+
+/// The git repository or crate the project itself is fetched from
+#[derive(Debug, Serialize)]
+pub struct Project {
+    pub source: ProjectSource,
+    pub uri: String,
+    pub rev: String,
+    pub branch: String,
+    /// the directory of the package in the repository
+    pub rel_dir: String,
+    /// the sha256 of the crate when fetched from crates.io
+    pub checksum: Option<String>,
+}
+
+/// Everything we have worked out about the recipe for a package. The
+/// recipe is rendered from this and `--format json` writes it out as is.
+#[derive(Debug, Serialize)]
+pub struct Recipe {
+    pub name: String,
+    pub version: String,
+    pub summary: String,
+    pub homepage: String,
+    /// the license in Yocto fmt
+    pub license: String,
+    /// the license ids of the package itself
+    pub licenses: Vec<String>,
+    pub license_files: Vec<license::LicenseFile>,
+    /// variables needed by licenses that aren't in common-licenses
+    pub license_extras: Vec<String>,
+    pub dependency_licenses: Vec<LicenseGroup>,
+    pub features: Vec<features::Feature>,
+    /// what the -sys crates need from Yocto
+    pub system_deps: sysdeps::SystemDeps,
+    pub project: Project,
+    /// the PV append for recipes that aren't built from a tag
+    pub git_srcpv: String,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Recipe {
+    /// the file BitBake expects the recipe to be in
+    pub fn file_name(&self) -> String {
+        format!("{}_{}.bb", self.name, self.version)
+    }
+
+    /// the file `render_with_crates_inc` expects the crates to be written to
+    pub fn crates_inc_file_name(&self) -> String {
+        format!("{}-crates.inc", self.name)
+    }
+
+    /// Renders the recipe with the template at `template_path` or the
+    /// built-in one when none is given
+    pub fn render(&self, template_path: Option<&Path>) -> CargoResult<String> {
+        self.render_recipe(
+            template_path,
+            self.all_src_uris(),
+            self.all_src_uri_extras(),
+            String::new(),
+        )
+    }
+
+    /// Renders the recipe like `render` but with the crates moved into
+    /// their own include file, providing the recipe and the include file
+    pub fn render_with_crates_inc(
+        &self,
+        template_path: Option<&Path>,
+    ) -> CargoResult<(String, String)> {
+        let ctx = CratesIncContext {
+            src_uri: multiline(&self.crate_uris()),
+            src_uri_extras: self.crate_checksums().join("\n"),
+            cargo_bitbake_ver: env!("CARGO_PKG_VERSION"),
+            crates: self.crates(),
+        };
+        let inc = template::render("crates.inc", template::CRATES_INC, ctx)?;
+
+        let recipe = self.render_recipe(
+            template_path,
+            self.src_uris(),
+            self.src_uri_extras(),
+            format!("require {}\n", self.crates_inc_file_name()),
+        )?;
+        Ok((recipe, inc))
+    }
+
+    /// Provides the recipe as pretty printed JSON
+    pub fn to_json(&self) -> CargoResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// renders the recipe with the given `SRC_URI` entries and variables
+    fn render_recipe(
+        &self,
+        template_path: Option<&Path>,
+        src_uris: Vec<String>,
+        src_uri_extras: Vec<String>,
+        crates_inc: String,
+    ) -> CargoResult<String> {
+        let lic_files = self
+            .license_files
+            .iter()
+            .map(|file| file.to_string())
+            .collect::<Vec<_>>();
+        let ctx = RecipeContext {
+            name: &self.name,
+            version: &self.version,
+            summary: &self.summary,
+            homepage: &self.homepage,
+            license: &self.license,
+            license_extras: self
+                .license_extras
+                .iter()
+                .map(|l| format!("{}\n", l))
+                .collect(),
+            dependency_licenses: dependency_licenses_comment(&self.dependency_licenses),
+            lic_files: multiline(&lic_files),
+            src_uri: multiline(&src_uris),
+            src_uri_extras: src_uri_extras.join("\n"),
+            crates_inc,
+            project_source: self.project.source,
+            project_rel_dir: &self.project.rel_dir,
+            project_src_uri: &self.project.uri,
+            project_src_rev: &self.project.rev,
+            project_src_branch: &self.project.branch,
+            project_checksum: self.project.checksum.as_deref().unwrap_or_default(),
+            git_srcpv: &self.git_srcpv,
+            packageconfig: features::packageconfig(&self.features),
+            system_deps: self.system_deps.to_bitbake(),
+            cargo_bitbake_ver: env!("CARGO_PKG_VERSION"),
+            features: &self.features,
+            system_dependencies: &self.system_deps,
+            licenses: &self.licenses,
+            dependency_license_entries: &self.dependency_licenses,
+            license_extra_entries: &self.license_extras,
+            lic_file_entries: &lic_files,
+            src_uri_entries: &src_uris,
+            src_uri_extra_entries: &src_uri_extras,
+            crates: self.crates(),
+        };
+
+        match template_path {
+            Some(path) => {
+                let source = template::load(path)?;
+                template::render(&path.display().to_string(), &source, ctx)
+            }
+            None => template::render("bitbake.bb", template::RECIPE, ctx),
+        }
+    }
+
+    /// the packages fetched from a crate registry
+    fn crates(&self) -> Vec<&Dependency> {
+        self.dependencies
+            .iter()
+            .filter(|d| d.source == Source::Registry)
+            .collect()
+    }
+
+    /// the `crate://` entries for the registry packages
+    fn crate_uris(&self) -> Vec<String> {
+        self.crates().iter().map(|c| c.uri.clone()).collect()
+    }
+
+    /// the `SRC_URI[...sha256sum]` entries for the registry packages
+    fn crate_checksums(&self) -> Vec<String> {
+        self.crates()
+            .iter()
+            .filter_map(|c| c.checksum_entry())
+            .collect()
+    }
+
+    /// entries for packages which are not fetched from a crate registry,
+    /// the crates of a git repository share a single entry
+    fn src_uris(&self) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .filter(|d| d.source != Source::Registry)
+            .map(|d| d.uri.clone())
+            .unique()
+            .collect()
+    }
+
+    /// SRCREVs and other variables needed by the non-registry entries
+    fn src_uri_extras(&self) -> Vec<String> {
+        let mut fetches = vec![];
+        let mut extras = vec![];
+        for dep in &self.dependencies {
+            if dep.fetch_name.is_some() && !fetches.contains(&dep.fetch_name) {
+                fetches.push(dep.fetch_name.clone());
+                extras.extend(dep.srcrev_entries());
+            }
+            extras.extend(dep.path_entry());
+        }
+        extras
+    }
+
+    /// all of the SRC_URI entries, used when the crates are kept in the recipe
+    fn all_src_uris(&self) -> Vec<String> {
+        self.crate_uris()
+            .into_iter()
+            .chain(self.src_uris())
+            .sorted()
+            .collect()
+    }
+
+    /// all of the SRC_URI variables, used when the crates are kept in the recipe
+    fn all_src_uri_extras(&self) -> Vec<String> {
+        self.crate_checksums()
+            .into_iter()
+            .chain(self.src_uri_extras())
+            .collect()
+    }
+}
+
+/// All of the values available to a recipe template
+#[derive(Serialize)]
+struct RecipeContext<'a> {
+    name: &'a str,
+    version: &'a str,
+    summary: &'a str,
+    homepage: &'a str,
+    license: &'a str,
+    license_extras: String,
+    dependency_licenses: String,
+    lic_files: String,
+    src_uri: String,
+    src_uri_extras: String,
+    crates_inc: String,
+    project_source: ProjectSource,
+    project_rel_dir: &'a str,
+    project_src_uri: &'a str,
+    project_src_rev: &'a str,
+    project_src_branch: &'a str,
+    project_checksum: &'a str,
+    git_srcpv: &'a str,
+    packageconfig: String,
+    system_deps: String,
+    cargo_bitbake_ver: &'a str,
+    /// the features of the package and if they are enabled by default
+    features: &'a [features::Feature],
+    /// the recipes, classes and variables making up `system_deps`
+    system_dependencies: &'a sysdeps::SystemDeps,
+    /// the license ids making up `license`
+    licenses: &'a [String],
+    /// the licenses of the dependencies and the crates under each of them
+    dependency_license_entries: &'a [LicenseGroup],
+    /// the lines making up `license_extras`
+    license_extra_entries: &'a [String],
+    /// the entries making up `lic_files`
+    lic_file_entries: &'a [String],
+    /// the entries making up `src_uri`
+    src_uri_entries: &'a [String],
+    /// the lines making up `src_uri_extras`
+    src_uri_extra_entries: &'a [String],
+    /// every crate fetched from a crate registry
+    crates: Vec<&'a Dependency>,
+}
+
+/// The values for the `{name}-crates.inc` template
+#[derive(Serialize)]
+struct CratesIncContext<'a> {
+    src_uri: String,
+    src_uri_extras: String,
+    cargo_bitbake_ver: &'a str,
+    crates: Vec<&'a Dependency>,
+}
+
+/// a comment block listing which crate carries which license
+fn dependency_licenses_comment(groups: &[LicenseGroup]) -> String {
+    if groups.is_empty() {
+        return String::new();
+    }
+
+    let mut comment = String::from("\n# Licenses of the crates built into this recipe:\n");
+    for group in groups {
+        comment.push_str(&format!(
+            "#   {}: {}\n",
+            group.license,
+            group.crates.join(" ")
+        ));
+    }
+    comment
+}
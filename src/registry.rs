@@ -0,0 +1,183 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use anyhow::anyhow;
+use cargo::GlobalContext;
+use cargo::core::{Dependency, SourceId};
+use cargo::sources::RegistrySource;
+use cargo::sources::source::{QueryKind, Source};
+use cargo::util::CargoResult;
+use cargo::util::cache_lock::CacheLockMode;
+use std::collections::HashSet;
+use std::task::Poll;
+
+/// the host bitbake's crate fetcher knows as crates.io
+pub const CRATES_IO_URL: &str = "crates.io";
+
+/// the suffix cargo appends to a download URL that has no markers
+const DL_SUFFIX: &str = "/{crate}/{version}/download";
+
+/// markers cargo substitutes in a registry's download URL
+const DL_MARKERS: &[&str] = &[
+    "{crate}",
+    "{version}",
+    "{prefix}",
+    "{lowerprefix}",
+    "{sha256-checksum}",
+];
+
+/// converts the `dl` value from a registry's config.json to the host
+/// (and path) used in a `crate://` URL. bitbake's crate fetcher downloads
+/// `crate://{host}/{name}/{version}` from
+/// `https://{host}/{name}/{version}/download` so anything else can't be
+/// expressed.
+pub fn dl_to_crate_host(dl: &str) -> Option<String> {
+    let dl = dl.trim_end_matches('/');
+
+    // an explicit template is fine as long as its the default layout
+    let dl = dl.strip_suffix(DL_SUFFIX).unwrap_or(dl);
+    if DL_MARKERS.iter().any(|m| dl.contains(m)) {
+        return None;
+    }
+
+    // the crate fetcher only downloads over https
+    dl.strip_prefix("https://")
+        .filter(|host| !host.is_empty())
+        .map(str::to_string)
+}
+
+/// the host of the registry index itself which is the best guess we
+/// can make when the registry's config.json doesn't help us
+fn index_host(source_id: SourceId) -> String {
+    let url = source_id.url();
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => url.to_string(),
+    }
+}
+
+/// loads the config.json of the remote registry `source_id`
+fn registry_dl(source_id: SourceId, gctx: &GlobalContext) -> CargoResult<String> {
+    if !source_id.is_remote_registry() {
+        return Err(anyhow!("{} is not a remote registry", source_id.url()));
+    }
+
+    // the registry index can only be read while holding the package cache lock
+    let _lock = gctx.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+    let mut source = RegistrySource::remote(source_id, &HashSet::new(), gctx)?;
+
+    let config = loop {
+        match source.config() {
+            Poll::Ready(config) => break config?,
+            Poll::Pending => source.block_until_ready()?,
+        }
+    };
+
+    config
+        .map(|c| c.dl)
+        .ok_or_else(|| anyhow!("No config.json in registry"))
+}
+
+/// Determines the host to use in `crate://` URLs for packages that come
+/// from the registry `source_id`, warning when it can't be expressed as
+/// something bitbake's crate fetcher can fetch
+pub fn crate_host(source_id: SourceId, gctx: &GlobalContext) -> String {
+    if source_id.is_crates_io() {
+        return CRATES_IO_URL.into();
+    }
+
+    let name = source_id.display_registry_name();
+    match registry_dl(source_id, gctx) {
+        Ok(dl) => dl_to_crate_host(&dl).unwrap_or_else(|| {
+            let _ = gctx.shell().warn(format!(
+                "the download URL '{}' of registry {} cannot be expressed as a crate:// URL",
+                dl, name
+            ));
+            index_host(source_id)
+        }),
+        Err(e) => {
+            let _ = gctx.shell().warn(format!(
+                "unable to read the configuration of registry {}: {}",
+                name, e
+            ));
+            index_host(source_id)
+        }
+    }
+}
+
+/// Looks up the checksum of version `version` of the crate `name` on
+/// crates.io, which is needed to fetch a package from there
+pub fn crates_io_checksum(name: &str, version: &str, gctx: &GlobalContext) -> CargoResult<String> {
+    let source_id = SourceId::crates_io_maybe_sparse_http(gctx)?;
+    let dep = Dependency::parse(name, Some(&format!("={}", version)), source_id)?;
+
+    // the registry index can only be read while holding the package cache lock
+    let _lock = gctx.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+    let mut source = RegistrySource::remote(source_id, &HashSet::new(), gctx)?;
+
+    let mut summaries = vec![];
+    loop {
+        match source.query(&dep, QueryKind::Exact, &mut |s| {
+            summaries.push(s.into_summary())
+        }) {
+            Poll::Ready(res) => break res?,
+            Poll::Pending => source.block_until_ready()?,
+        }
+    }
+
+    summaries
+        .iter()
+        .find_map(|s| s.checksum())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("{} {} is not published on crates.io", name, version))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dl_no_markers() {
+        assert_eq!(
+            dl_to_crate_host("https://crates.example.com/api/v1/crates"),
+            Some("crates.example.com/api/v1/crates".into())
+        );
+    }
+
+    #[test]
+    fn dl_trailing_slash() {
+        assert_eq!(
+            dl_to_crate_host("https://crates.example.com/dl/"),
+            Some("crates.example.com/dl".into())
+        );
+    }
+
+    #[test]
+    fn dl_default_markers() {
+        assert_eq!(
+            dl_to_crate_host("https://crates.example.com/dl/{crate}/{version}/download"),
+            Some("crates.example.com/dl".into())
+        );
+    }
+
+    #[test]
+    fn dl_custom_markers() {
+        assert_eq!(
+            dl_to_crate_host("https://crates.example.com/{prefix}/{crate}-{version}.crate"),
+            None
+        );
+    }
+
+    #[test]
+    fn dl_not_https() {
+        assert_eq!(dl_to_crate_host("http://crates.example.com/dl"), None);
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use anyhow::Context as _;
+use cargo::GlobalContext;
+use cargo::core::dependency::DepKind;
+use cargo::core::{PackageId, Resolve};
+use cargo::util::CargoResult;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// the mappings for the -sys crates and build tools we know about
+const BUILTIN: &str = include_str!("sys-crates.toml");
+
+/// What a -sys crate needs from Yocto to build against the system library
+/// or a crate used by build scripts needs to run on the build host
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct SysCrate {
+    /// the `links` key of the crate, so forks of it are matched as well
+    links: Option<String>,
+    /// only applies when the crate is used as a build-dependency
+    build: bool,
+    depends: Vec<String>,
+    inherit: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+/// The database of -sys crates and build tools keyed by crate name
+#[derive(Debug)]
+pub struct SysCrates {
+    crates: BTreeMap<String, SysCrate>,
+}
+
+impl SysCrates {
+    /// loads the built-in mappings and then the ones at `path`, which
+    /// replace any built-in mapping of the same name
+    pub fn new(path: Option<&Path>) -> CargoResult<Self> {
+        let mut crates = parse(BUILTIN).context("Unable to parse the built-in -sys crates")?;
+        if let Some(path) = path {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Unable to read '{}'", path.display()))?;
+            crates.extend(
+                parse(&contents)
+                    .with_context(|| format!("Unable to parse '{}'", path.display()))?,
+            );
+        }
+        Ok(Self { crates })
+    }
+
+    /// the mapping for a crate called `name` with the `links` key `links`
+    fn find(&self, name: &str, links: Option<&str>) -> Option<&SysCrate> {
+        self.crates.get(name).or_else(|| {
+            let links = links?;
+            self.crates
+                .values()
+                .find(|c| c.links.as_deref() == Some(links))
+        })
+    }
+
+    /// Works out what the `deps` from `resolve` need from Yocto
+    pub fn system_deps(
+        &self,
+        gctx: &GlobalContext,
+        resolve: &Resolve,
+        deps: &[PackageId],
+    ) -> CargoResult<SystemDeps> {
+        // the crates some build script of the dependencies uses
+        let build_deps = deps
+            .iter()
+            .flat_map(|&pkg| resolve.deps(pkg))
+            .filter(|(_, d)| d.iter().any(|d| d.kind() == DepKind::Build))
+            .map(|(dep_id, _)| dep_id)
+            .collect::<HashSet<_>>();
+
+        let mut system_deps = SystemDeps::default();
+        for &pkg in deps {
+            let links = resolve.summary(pkg).links();
+            match self.find(pkg.name().as_str(), links.as_deref()) {
+                Some(sys) if !sys.build || build_deps.contains(&pkg) => {
+                    for conflict in system_deps.add(pkg.name().as_str(), sys) {
+                        gctx.shell().warn(conflict)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(system_deps)
+    }
+}
+
+/// parses a table of -sys crates
+fn parse(contents: &str) -> CargoResult<BTreeMap<String, SysCrate>> {
+    Ok(toml::from_str(contents)?)
+}
+
+/// The recipes, classes and environment the dependencies need
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SystemDeps {
+    pub depends: BTreeSet<String>,
+    pub inherit: BTreeSet<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl SystemDeps {
+    /// the comment the statements start with, which is how `--update`
+    /// tells them apart from additions made by hand
+    pub const COMMENT: &'static str = "# needed by the -sys crates and build scripts";
+
+    /// adds what the crate `name` needs according to `sys`, returning a
+    /// message for every variable it sets to a different value
+    fn add(&mut self, name: &str, sys: &SysCrate) -> Vec<String> {
+        self.depends.extend(sys.depends.iter().cloned());
+        self.inherit.extend(sys.inherit.iter().cloned());
+        let mut conflicts = vec![];
+        for (var, value) in &sys.env {
+            match self.env.insert(var.clone(), value.clone()) {
+                Some(old) if old != *value => conflicts.push(format!(
+                    "{} sets {} to \"{}\" instead of \"{}\"",
+                    name, var, value, old
+                )),
+                _ => {}
+            }
+        }
+        conflicts
+    }
+
+    /// the BitBake statements that provide the system dependencies
+    pub fn to_bitbake(&self) -> String {
+        if self.depends.is_empty() && self.inherit.is_empty() && self.env.is_empty() {
+            return String::new();
+        }
+
+        let mut lines = vec![Self::COMMENT.to_string()];
+        if !self.inherit.is_empty() {
+            lines.push(format!("inherit {}", self.inherit.iter().join(" ")));
+        }
+        if !self.depends.is_empty() {
+            lines.push(format!("DEPENDS += \"{}\"", self.depends.iter().join(" ")));
+        }
+        lines.extend(
+            self.env
+                .iter()
+                .map(|(var, value)| format!("export {} = \"{}\"", var, value)),
+        );
+        lines.into_iter().map(|l| l + "\n").collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_crates() {
+        let sys = SysCrates::new(None).unwrap();
+        let openssl = sys.find("openssl-sys", Some("openssl")).unwrap();
+        assert_eq!(openssl.depends, vec!["openssl"]);
+        assert_eq!(openssl.env["OPENSSL_NO_VENDOR"], "1");
+        // a fork is found through its links key
+        assert_eq!(
+            sys.find("my-zlib-sys", Some("z")).unwrap().depends,
+            vec!["zlib"]
+        );
+        assert!(sys.find("serde", None).is_none());
+        let bindgen = sys.find("bindgen", None).unwrap();
+        assert!(bindgen.build);
+        assert_eq!(bindgen.depends, vec!["clang-native"]);
+        assert!(bindgen.env.contains_key("LIBCLANG_PATH"));
+    }
+
+    #[test]
+    fn user_crates_replace_builtin() {
+        let mut sys = SysCrates::new(None).unwrap();
+        sys.crates.extend(
+            parse("[libz-sys]\ndepends = [\"zlib-ng\"]\n\n[foo-sys]\nlinks = \"foo\"\ndepends = [\"libfoo\"]\n")
+                .unwrap(),
+        );
+        assert_eq!(sys.find("libz-sys", None).unwrap().depends, vec!["zlib-ng"]);
+        assert_eq!(sys.find("foo-sys", None).unwrap().depends, vec!["libfoo"]);
+        assert!(parse("[foo-sys]\ndepend = [\"libfoo\"]\n").is_err());
+    }
+
+    #[test]
+    fn bitbake_statements() {
+        let sys = SysCrates::new(None).unwrap();
+        let mut deps = SystemDeps::default();
+        assert_eq!(deps.to_bitbake(), "");
+        deps.add("openssl-sys", sys.find("openssl-sys", None).unwrap());
+        assert!(
+            deps.add("libz-sys", sys.find("libz-sys", None).unwrap())
+                .is_empty()
+        );
+        assert_eq!(
+            deps.to_bitbake(),
+            "# needed by the -sys crates and build scripts\n\
+             inherit pkgconfig\n\
+             DEPENDS += \"openssl zlib\"\n\
+             export OPENSSL_NO_VENDOR = \"1\"\n"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use anyhow::{Context as _, anyhow};
+use cargo::util::CargoResult;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// the recipe template used when one isn't supplied by the user
+pub const RECIPE: &str = include_str!("bitbake.template");
+
+/// the template used for the `{name}-crates.inc` file
+pub const CRATES_INC: &str = include_str!("crates.inc.template");
+
+/// Reads in a user supplied template from `path`
+pub fn load(path: &Path) -> CargoResult<String> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Unable to read template '{}'", path.display()))
+}
+
+/// Renders the template `source`, which is referred to as `name` in
+/// any errors, with the values provided by `ctx`
+pub fn render<S: Serialize>(name: &str, source: &str, ctx: S) -> CargoResult<String> {
+    let mut env = Environment::new();
+    // a placeholder we don't provide a value for is a mistake in the
+    // template so make sure it is an error instead of an empty string
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    // templates are expected to end with a newline like any other file
+    env.set_keep_trailing_newline(true);
+
+    env.add_template(name, source)
+        .with_context(|| format!("Unable to parse template '{}'", name))?;
+    env.get_template(name)?
+        .render(ctx)
+        // the alternate form includes the offending line of the template
+        .map_err(|e| anyhow!("{:#}", e))
+        .with_context(|| format!("Unable to render template '{}'", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use minijinja::context;
+
+    #[test]
+    fn render_values() {
+        let out = render(
+            "test",
+            "SRCREV = \"{{ rev }}\"\n{% for c in crates %}{{ c }} {% endfor %}\n",
+            context!(rev => "abc", crates => vec!["a", "b"]),
+        )
+        .unwrap();
+        assert_eq!(out, "SRCREV = \"abc\"\na b \n");
+    }
+
+    #[test]
+    fn bitbake_variables_untouched() {
+        let out = render("test", "S = \"${WORKDIR}/git\"", context!()).unwrap();
+        assert_eq!(out, "S = \"${WORKDIR}/git\"");
+    }
+
+    #[test]
+    fn unknown_placeholder() {
+        let err = render("test", "{{ nope }}", context!(rev => "abc")).unwrap_err();
+        assert!(format!("{:#}", err).contains("undefined value"));
+    }
+}
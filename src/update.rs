@@ -0,0 +1,571 @@
+/*
+ * Copyright 2016-2017 Doug Goldstein <cardoe@cardoe.com>
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use anyhow::anyhow;
+use cargo::util::CargoResult;
+use cargo_bitbake::SystemDeps;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fs;
+use std::path::PathBuf;
+
+lazy_static! {
+    /// the variable being set by a BitBake assignment
+    static ref ASSIGNMENT: Regex =
+        Regex::new(r"^\s*([A-Za-z0-9_\-\+\$\{\}\[\]\.:/]+?)\s*(\?\?=|\?=|:=|\+=|=\+|\.=|=\.|=)")
+            .unwrap();
+}
+
+/// The parts of a recipe that cargo-bitbake generates from Cargo.lock and
+/// the crate's metadata
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Block {
+    /// the `SRC_URI` entries for the dependencies
+    SrcUri,
+    /// the checksums, `SRCREV`s and paths of the dependencies
+    SrcUriExtras,
+    /// the `SRCREV` of the project itself
+    Srcrev,
+    /// the `crate://` entry and checksum of the project itself when fetched
+    /// from crates.io
+    CrateSrcUri,
+    /// the `LIC_FILES_CHKSUM` entries
+    LicFiles,
+    /// the `require` of the crates include file
+    CratesInc,
+    /// the `PACKAGECONFIG` options for the features and how they are passed
+    /// to cargo
+    Features,
+    /// the recipes, classes and variables the -sys crates and build tools
+    /// need
+    SystemDeps,
+}
+
+/// A single statement of a recipe, which is a line or several lines
+/// joined by a trailing `\`
+#[derive(Debug)]
+struct Statement<'a> {
+    text: &'a str,
+    block: Option<Block>,
+}
+
+/// Splits `recipe` up into its statements, keeping their line endings
+fn statements(recipe: &str) -> Vec<Statement<'_>> {
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut end = 0;
+    for line in recipe.split_inclusive('\n') {
+        end += line.len();
+        if !line.trim_end().ends_with('\\') {
+            statements.push(&recipe[start..end]);
+            start = end;
+        }
+    }
+    if start < recipe.len() {
+        statements.push(&recipe[start..]);
+    }
+
+    let mut statements = statements
+        .into_iter()
+        .map(|text| Statement {
+            text,
+            block: block(text),
+        })
+        .collect::<Vec<_>>();
+
+    // the system dependencies follow their comment as a single `inherit`,
+    // a single `DEPENDS` and then the exports, so a `DEPENDS` added by hand
+    // right after them isn't mistaken for theirs
+    const KINDS: [&str; 3] = ["inherit ", "DEPENDS += ", "export "];
+    let mut previous: Option<Option<usize>> = None;
+    for statement in &mut statements {
+        let trimmed = statement.text.trim();
+        let kind = KINDS.iter().position(|k| trimmed.starts_with(k));
+        previous = match (previous, kind) {
+            _ if trimmed == SystemDeps::COMMENT => Some(None),
+            (Some(prev), Some(kind))
+                if prev.is_none_or(|p| kind > p || KINDS[kind] == "export ") =>
+            {
+                Some(Some(kind))
+            }
+            _ => None,
+        };
+        if previous.is_some() {
+            statement.block = Some(Block::SystemDeps);
+        }
+    }
+
+    // the checksum of the project's own crate goes along with its entry
+    // instead of with the checksums of the dependencies
+    let checksum = statements
+        .iter()
+        .filter(|s| s.block == Some(Block::CrateSrcUri))
+        .find_map(|s| own_checksum_var(s.text));
+    if let Some(checksum) = checksum {
+        for statement in &mut statements {
+            if ASSIGNMENT
+                .captures(statement.text)
+                .is_some_and(|c| c[1] == checksum)
+            {
+                statement.block = Some(Block::CrateSrcUri);
+            }
+        }
+    }
+    statements
+}
+
+/// the variable holding the checksum of the `crate://` entry of the project
+fn own_checksum_var(statement: &str) -> Option<String> {
+    let uri = statement.split('"').nth(1)?.strip_prefix("crate://")?;
+    let mut parts = uri.rsplitn(3, '/');
+    let version = parts.next()?;
+    let name = parts.next()?;
+    Some(format!("SRC_URI[{}-{}.sha256sum]", name, version))
+}
+
+/// Determines which of the blocks we own that `statement` belongs to, if any
+fn block(statement: &str) -> Option<Block> {
+    let trimmed = statement.trim();
+    if trimmed.starts_with("require ") && trimmed.ends_with("-crates.inc") {
+        return Some(Block::CratesInc);
+    }
+
+    let assignment = ASSIGNMENT.captures(statement)?;
+    let (var, op) = (&assignment[1], &assignment[2]);
+    if var == "SRC_URI" && !statement.trim_end().contains('\n') {
+        // a single entry is the package itself, which is only ours when it
+        // is fetched from crates.io
+        statement
+            .contains("\"crate://")
+            .then_some(Block::CrateSrcUri)
+    } else if var == "SRC_URI" {
+        // the dependencies are always listed one per line and are either
+        // crates or git checkouts we've placed in their own directory
+        let (first, rest) = statement.split_once('\n')?;
+        let entries = rest
+            .split_whitespace()
+            .filter(|e| *e != "\\" && *e != "\"")
+            .collect::<Vec<_>>();
+        (first.trim_end().ends_with('\\')
+            && (entries.is_empty()
+                || entries
+                    .iter()
+                    .any(|e| e.starts_with("crate://") || e.contains("destsuffix="))))
+        .then_some(Block::SrcUri)
+    } else if (var.starts_with("SRC_URI[") && var.ends_with(".sha256sum]"))
+        || var.starts_with("SRCREV_")
+        || var == "EXTRA_OECARGO_PATHS"
+    {
+        Some(Block::SrcUriExtras)
+    } else if var == "SRCREV" {
+        Some(Block::Srcrev)
+    } else if var == "LIC_FILES_CHKSUM" {
+        Some(Block::LicFiles)
+    } else if (var == "PACKAGECONFIG" && op == "??=")
+        || var.starts_with("PACKAGECONFIG[")
+        || var == "CARGO_FEATURES"
+        || (var == "CARGO_BUILD_FLAGS" && statement.contains("'${CARGO_FEATURES}'"))
+    {
+        Some(Block::Features)
+    } else {
+        None
+    }
+}
+
+/// the variable assigned by `statement`, if it is an assignment
+fn variable(statement: &str) -> Option<&str> {
+    Some(ASSIGNMENT.captures(statement)?.get(1)?.as_str())
+}
+
+/// Updates the `existing` recipe with the blocks we own from the freshly
+/// `generated` one, leaving everything else in it alone. Each block is
+/// written where it first appeared in the existing recipe and a block that
+/// is new is placed after what precedes it in the generated one.
+/// The `PACKAGECONFIG` entries of features that still exist are kept as
+/// they are since they are where the recipe adds the dependencies of a
+/// feature.
+pub fn merge(existing: &str, generated: &str) -> String {
+    let existing = statements(existing);
+    let generated = statements(generated);
+    let blocks = generated
+        .iter()
+        .filter_map(|s| s.block)
+        .fold(vec![], |mut blocks, b| {
+            if !blocks.contains(&b) {
+                blocks.push(b);
+            }
+            blocks
+        });
+    let block_text = |block: Block| {
+        generated
+            .iter()
+            .filter(|s| s.block == Some(block))
+            .map(|s| {
+                variable(s.text)
+                    .filter(|var| var.starts_with("PACKAGECONFIG["))
+                    .and_then(|var| {
+                        existing
+                            .iter()
+                            .find(|e| e.block == Some(block) && variable(e.text) == Some(var))
+                    })
+                    .map_or(s.text, |e| e.text)
+            })
+            .collect::<String>()
+    };
+
+    let mut merged: Vec<(Option<Block>, String)> = vec![];
+    let mut written = vec![];
+    for statement in &existing {
+        match statement.block {
+            Some(block) if written.contains(&block) => {}
+            Some(block) => {
+                written.push(block);
+                merged.push((Some(block), block_text(block)));
+            }
+            None => merged.push((None, statement.text.to_string())),
+        }
+    }
+
+    // anything we haven't written yet goes after the statement preceding
+    // it when the recipe still has that one and otherwise after the block
+    // preceding it
+    for (idx, &block) in blocks.iter().enumerate() {
+        if written.contains(&block) {
+            continue;
+        }
+
+        let preceding = generated
+            .iter()
+            .take_while(|s| s.block != Some(block))
+            .last()
+            .filter(|s| s.block.is_none());
+        let pos = preceding
+            .and_then(|prev| {
+                merged
+                    .iter()
+                    .position(|(b, t)| b.is_none() && *t == prev.text)
+            })
+            .or_else(|| {
+                blocks[..idx]
+                    .iter()
+                    .rev()
+                    .find_map(|prev| merged.iter().position(|(b, _)| *b == Some(*prev)))
+            })
+            .map(|pos| pos + 1);
+        let text = block_text(block);
+        match pos {
+            Some(pos) => merged.insert(pos, (Some(block), text)),
+            None => {
+                // make sure the block starts on a line of its own
+                if merged.last().is_some_and(|(_, t)| !t.ends_with('\n')) {
+                    merged.push((None, "\n".into()));
+                }
+                merged.push((Some(block), text));
+            }
+        }
+        written.push(block);
+    }
+
+    merged.into_iter().map(|(_, text)| text).collect()
+}
+
+/// Finds the existing recipe for the package `name` in the current
+/// directory, which may be for a different version than the one we are
+/// generating
+pub fn find_recipe(name: &str) -> CargoResult<Option<PathBuf>> {
+    let prefix = format!("{}_", name);
+    let mut recipes = fs::read_dir(".")?
+        .flatten()
+        .map(|e| PathBuf::from(e.file_name()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|f| f.to_str())
+                .and_then(|f| f.strip_prefix(&prefix))
+                .and_then(|f| f.strip_suffix(".bb"))
+                .is_some_and(|version| !version.contains('_'))
+        })
+        .collect::<Vec<_>>();
+
+    match recipes.len() {
+        0 | 1 => Ok(recipes.pop()),
+        _ => Err(anyhow!(
+            "Found more than one recipe for {} to update: {}",
+            name,
+            recipes
+                .iter()
+                .map(|r| r.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXISTING: &str = r#"inherit cargo
+
+SRC_URI += "git://github.com/foo/foo.git;protocol=https;nobranch=1"
+SRCREV = "aaaa"
+S = "${WORKDIR}/git"
+
+SRC_URI += " \
+    crate://crates.io/itoa/1.0.17 \
+"
+
+SRC_URI[itoa-1.0.17.sha256sum] = "1111"
+
+LIC_FILES_CHKSUM = " \
+    file://LICENSE;md5=generateme \
+"
+
+LICENSE = "MIT"
+DEPENDS += "openssl"
+
+do_install:append() {
+    install -d ${D}${sysconfdir}
+}
+"#;
+
+    const GENERATED: &str = r#"inherit cargo
+
+SRC_URI += "git://github.com/foo/foo.git;protocol=https;nobranch=1"
+SRCREV = "bbbb"
+S = "${WORKDIR}/git"
+
+SRC_URI += " \
+    crate://crates.io/itoa/1.0.18 \
+    crate://crates.io/libc/0.2.0 \
+"
+
+SRC_URI[itoa-1.0.18.sha256sum] = "2222"
+SRC_URI[libc-0.2.0.sha256sum] = "3333"
+
+LIC_FILES_CHKSUM = " \
+    file://LICENSE;md5=abcd \
+"
+
+LICENSE = "MIT"
+"#;
+
+    #[test]
+    fn statement_blocks() {
+        let blocks = statements(GENERATED)
+            .iter()
+            .filter_map(|s| s.block)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Srcrev,
+                Block::SrcUri,
+                Block::SrcUriExtras,
+                Block::SrcUriExtras,
+                Block::LicFiles,
+            ]
+        );
+        assert_eq!(block("SRC_URI += \"file://fix.patch\"\n"), None);
+        assert_eq!(
+            block("SRC_URI += \" \\\n    file://fix.patch \\\n\"\n"),
+            None
+        );
+        assert_eq!(block("SRC_URI += \" \\\n\"\n"), Some(Block::SrcUri));
+        assert_eq!(
+            block("SRCREV_FORMAT .= \"_foo\"\n"),
+            Some(Block::SrcUriExtras)
+        );
+        assert_eq!(block("require foo-crates.inc\n"), Some(Block::CratesInc));
+        assert_eq!(
+            block("SRC_URI += \"crate://crates.io/foo/0.1.0\"\n"),
+            Some(Block::CrateSrcUri)
+        );
+        assert_eq!(
+            block("SRC_URI += \"git://github.com/foo/foo.git;protocol=https\"\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn merge_keeps_manual_edits() {
+        let merged = merge(EXISTING, GENERATED);
+        assert_eq!(
+            merged,
+            r#"inherit cargo
+
+SRC_URI += "git://github.com/foo/foo.git;protocol=https;nobranch=1"
+SRCREV = "bbbb"
+S = "${WORKDIR}/git"
+
+SRC_URI += " \
+    crate://crates.io/itoa/1.0.18 \
+    crate://crates.io/libc/0.2.0 \
+"
+
+SRC_URI[itoa-1.0.18.sha256sum] = "2222"
+SRC_URI[libc-0.2.0.sha256sum] = "3333"
+
+LIC_FILES_CHKSUM = " \
+    file://LICENSE;md5=abcd \
+"
+
+LICENSE = "MIT"
+DEPENDS += "openssl"
+
+do_install:append() {
+    install -d ${D}${sysconfdir}
+}
+"#
+        );
+        assert_eq!(merge(&merged, GENERATED), merged);
+    }
+
+    #[test]
+    fn merge_crates_io_recipe() {
+        let generated = r#"inherit cargo
+
+SRC_URI += "crate://crates.io/foo/0.2.0"
+SRC_URI[foo-0.2.0.sha256sum] = "aaaa"
+S = "${WORKDIR}/${BP}"
+
+SRC_URI += " \
+    crate://crates.io/libc/0.2.0 \
+"
+
+SRC_URI[libc-0.2.0.sha256sum] = "3333"
+"#;
+        assert_eq!(merge(generated, generated), generated);
+
+        let existing = generated.replace("foo/0.2.0", "foo/0.1.0").replace(
+            "foo-0.2.0.sha256sum] = \"aaaa",
+            "foo-0.1.0.sha256sum] = \"bbbb",
+        );
+        assert_eq!(merge(&existing, generated), generated);
+    }
+
+    #[test]
+    fn merge_system_deps() {
+        let existing = r#"CARGO_BUILD_FLAGS += "-p foo"
+# needed by the -sys crates and build scripts
+inherit pkgconfig
+DEPENDS += "openssl"
+export OPENSSL_NO_VENDOR = "1"
+
+DEPENDS += "zlib"
+"#;
+        let generated = r#"CARGO_BUILD_FLAGS += "-p foo"
+# needed by the -sys crates and build scripts
+inherit pkgconfig
+DEPENDS += "libgit2 openssl"
+export LIBGIT2_NO_VENDOR = "1"
+export OPENSSL_NO_VENDOR = "1"
+"#;
+        assert_eq!(
+            merge(existing, generated),
+            format!("{}\nDEPENDS += \"zlib\"\n", generated)
+        );
+        // the dependencies on -sys crates went away
+        assert_eq!(
+            merge(existing, "CARGO_BUILD_FLAGS += \"-p foo\"\n"),
+            "CARGO_BUILD_FLAGS += \"-p foo\"\n\nDEPENDS += \"zlib\"\n"
+        );
+        // and came back to a recipe that didn't have any
+        assert_eq!(
+            merge(
+                "CARGO_BUILD_FLAGS += \"-p foo\"\n\nDEPENDS += \"zlib\"\n",
+                generated
+            ),
+            format!("{}\nDEPENDS += \"zlib\"\n", generated)
+        );
+    }
+
+    #[test]
+    fn merge_build_tools() {
+        let existing = r#"CARGO_BUILD_FLAGS += "-p foo"
+# needed by the -sys crates and build scripts
+DEPENDS += "clang-native"
+export LIBCLANG_PATH = "${STAGING_LIBDIR_NATIVE}"
+DEPENDS += "protobuf"
+"#;
+        let generated = r#"CARGO_BUILD_FLAGS += "-p foo"
+# needed by the -sys crates and build scripts
+DEPENDS += "protobuf-native"
+export PROTOC = "${STAGING_BINDIR_NATIVE}/protoc"
+"#;
+        assert_eq!(
+            merge(existing, generated),
+            format!("{}DEPENDS += \"protobuf\"\n", generated)
+        );
+    }
+
+    #[test]
+    fn merge_features() {
+        let existing = r#"CARGO_BUILD_FLAGS += "-p foo"
+PACKAGECONFIG ??= "std"
+PACKAGECONFIG[std] = ""
+PACKAGECONFIG[tls] = ",,openssl"
+CARGO_FEATURES = "${PACKAGECONFIG}"
+CARGO_BUILD_FLAGS += "--no-default-features --features '${CARGO_FEATURES}'"
+
+PACKAGECONFIG:append = " tls"
+"#;
+        let generated = r#"CARGO_BUILD_FLAGS += "-p foo"
+PACKAGECONFIG ??= "alloc"
+PACKAGECONFIG[alloc] = ""
+PACKAGECONFIG[tls] = ""
+CARGO_FEATURES = "${PACKAGECONFIG}"
+CARGO_BUILD_FLAGS += "--no-default-features --features '${CARGO_FEATURES}'"
+"#;
+        assert_eq!(
+            merge(existing, generated),
+            r#"CARGO_BUILD_FLAGS += "-p foo"
+PACKAGECONFIG ??= "alloc"
+PACKAGECONFIG[alloc] = ""
+PACKAGECONFIG[tls] = ",,openssl"
+CARGO_FEATURES = "${PACKAGECONFIG}"
+CARGO_BUILD_FLAGS += "--no-default-features --features '${CARGO_FEATURES}'"
+
+PACKAGECONFIG:append = " tls"
+"#
+        );
+        // a crate without features any more loses all of them
+        assert_eq!(
+            merge(existing, "CARGO_BUILD_FLAGS += \"-p foo\"\n"),
+            "CARGO_BUILD_FLAGS += \"-p foo\"\n\nPACKAGECONFIG:append = \" tls\"\n"
+        );
+    }
+
+    #[test]
+    fn merge_build_metadata_versions() {
+        let existing = "SRC_URI += \" \\\n    crate://crates.io/curl-sys/0.4.90+curl-8.21.0 \\\n\"\n\nSRC_URI[curl-sys-0.4.90+curl-8.21.0.sha256sum] = \"1111\"\n";
+        let generated = existing
+            .replace("0.4.90+curl-8.21.0", "0.4.91+curl-8.22.0")
+            .replace("1111", "2222");
+        assert_eq!(
+            block("SRC_URI[curl-sys-0.4.91+curl-8.22.0.sha256sum] = \"2222\"\n"),
+            Some(Block::SrcUriExtras)
+        );
+        assert_eq!(
+            &ASSIGNMENT.captures("SRC_URI+=\"x\"\n").unwrap()[1],
+            "SRC_URI"
+        );
+        assert_eq!(merge(existing, &generated), generated);
+    }
+
+    #[test]
+    fn merge_new_and_removed_blocks() {
+        let existing = "SRC_URI += \" \\\n\"\n\nEXTRA_OECARGO_PATHS += \"${WORKDIR}/bar\"\nDEPENDS += \"zlib\"\n";
+        let generated = "SRC_URI += \" \\\n    crate://crates.io/libc/0.2.0 \\\n\"\nSRC_URI[libc-0.2.0.sha256sum] = \"3333\"\nLIC_FILES_CHKSUM = \"file://LICENSE;md5=abcd\"\n";
+        assert_eq!(
+            merge(existing, generated),
+            "SRC_URI += \" \\\n    crate://crates.io/libc/0.2.0 \\\n\"\n\nSRC_URI[libc-0.2.0.sha256sum] = \"3333\"\nLIC_FILES_CHKSUM = \"file://LICENSE;md5=abcd\"\nDEPENDS += \"zlib\"\n"
+        );
+    }
+}